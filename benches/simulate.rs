@@ -0,0 +1,59 @@
+// Throughput of `SandGrid::simulate` at a few fill densities, in simulated
+// cells per second.
+//
+// Run with `cargo bench --bench simulate`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_sand_sim::material::Material;
+use rust_sand_sim::sand_grid::SandGrid;
+use std::hint::black_box;
+
+const GRID_SIZE: usize = 256;
+const DENSITIES: [f64; 3] = [0.1, 0.5, 0.9];
+const DT: f32 = 1.0 / 60.0;
+const WARM_UP_STEPS: usize = 10;
+
+// A grid with `density` of its cells filled with sand at random, the same
+// layout on every run
+fn filled_grid(density: f64) -> SandGrid {
+    let mut grid = SandGrid::new(GRID_SIZE, GRID_SIZE).expect("Benchmark grid fits the size limit");
+    let mut rng = StdRng::seed_from_u64(0);
+    for y in 0..GRID_SIZE {
+        for x in 0..GRID_SIZE {
+            if rng.gen_bool(density) {
+                grid.set_material_at(x, y, Material::Sand);
+            }
+        }
+    }
+    for _ in 0..WARM_UP_STEPS {
+        grid.simulate(DT);
+    }
+    grid
+}
+
+fn bench_simulate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulate");
+    group.throughput(Throughput::Elements((GRID_SIZE * GRID_SIZE) as u64));
+    for density in DENSITIES {
+        //every step starts from the same unsettled grid, a settled pile would
+        //measure something else
+        let cells = filled_grid(density).copy_region(0, 0, GRID_SIZE, GRID_SIZE);
+        group.bench_with_input(BenchmarkId::from_parameter(format!("{:.0}%", density * 100.0)), &cells, |b, cells| {
+            b.iter_batched_ref(
+                || {
+                    let mut grid = SandGrid::new(GRID_SIZE, GRID_SIZE).expect("Benchmark grid fits the size limit");
+                    grid.paste_region(cells);
+                    grid
+                },
+                |grid| grid.simulate(black_box(DT)),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_simulate);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+// Draws a source texture over the whole target, used to downsample mip levels
+// and to copy or tonemap the offscreen color buffer to the surface
+
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var result: VertexOutput;
+    result.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    result.tex_coord = uv;
+    return result;
+}
+
+@group(0)
+@binding(0)
+var r_source: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var s_sampler: sampler;
+
+// Alpha weighted average of the 4 source texels under the target pixel, a plain
+// average would darken opaque cells next to transparent ones, whose color is black
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(r_source));
+    var sum = vec4<f32>(0.0);
+    for (var i = 0u; i < 4u; i++) {
+        let offset = vec2<f32>(f32(i & 1u) * 2.0 - 1.0, f32(i >> 1u) * 2.0 - 1.0) * half_texel;
+        let color = textureSample(r_source, s_sampler, vertex.tex_coord + offset);
+        sum += vec4<f32>(color.rgb * color.a, color.a);
+    }
+    if sum.a <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(sum.rgb / sum.a, sum.a / 4.0);
+}
+
+// The source as it is, the surface clamps colors above 1.0
+@fragment
+fn fs_copy(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(r_source, s_sampler, vertex.tex_coord);
+}
+
+// Colors above this are compressed towards 1.0, the ones below are left as they are
+const TONEMAP_KNEE: f32 = 0.8;
+
+// Brings the linear HDR colors back in the displayable range. Colors up to the
+// knee are kept so the regular materials look the same as without HDR, brighter
+// ones roll off smoothly instead of clipping, saturated channels going to white.
+@fragment
+fn fs_tonemap(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(r_source, s_sampler, vertex.tex_coord);
+    let over = max(color.rgb - vec3<f32>(TONEMAP_KNEE), vec3<f32>(0.0));
+    let shoulder = TONEMAP_KNEE + (1.0 - TONEMAP_KNEE) * (vec3<f32>(1.0) - exp(-over / (1.0 - TONEMAP_KNEE)));
+    return vec4<f32>(select(color.rgb, shoulder, color.rgb > vec3<f32>(TONEMAP_KNEE)), color.a);
+}
@@ -0,0 +1,73 @@
+// Bloom post process: the parts of the scene brighter than a threshold are
+// extracted to a half size texture, blurred and added back on top of the scene
+
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var result: VertexOutput;
+    result.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    result.tex_coord = uv;
+    return result;
+}
+
+@group(0)
+@binding(0)
+var r_source: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var s_sampler: sampler;
+
+struct BloomParams {
+    threshold: f32, //brightest channel a color needs to go over to glow
+    intensity: f32, //scale of the blurred glow added back to the scene
+};
+
+@group(1)
+@binding(0)
+var<uniform> params: BloomParams;
+
+// Keeps the part of the color above the threshold, colors just over it glow faintly
+@fragment
+fn fs_extract(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(r_source, s_sampler, vertex.tex_coord).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let excess = max(brightness - params.threshold, 0.0);
+    return vec4<f32>(color * excess / max(brightness, 0.0001), 1.0);
+}
+
+// 9 tap gaussian along `direction`, in texels
+fn blur(tex_coord: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let step = direction / vec2<f32>(textureDimensions(r_source));
+    var sum = textureSample(r_source, s_sampler, tex_coord).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        sum += textureSample(r_source, s_sampler, tex_coord + offset).rgb * weights[i];
+        sum += textureSample(r_source, s_sampler, tex_coord - offset).rgb * weights[i];
+    }
+    return vec4<f32>(sum, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(vertex.tex_coord, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(vertex.tex_coord, vec2<f32>(0.0, 1.0));
+}
+
+// The blurred glow, blended additively over the scene
+@fragment
+fn fs_composite(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let glow = textureSample(r_source, s_sampler, vertex.tex_coord).rgb;
+    return vec4<f32>(glow * params.intensity, 0.0);
+}
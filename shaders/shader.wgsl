@@ -2,6 +2,7 @@
 struct VertexOutput {
     @location(0) fragColor: vec3<f32>,
     @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
     @builtin(position) position: vec4<f32>,
 };
 
@@ -21,11 +22,33 @@ fn vs_main(
     @location(0) position: vec3<f32>,
     @location(1) color: vec3<f32>,
     @location(2) tex_coord: vec2<f32>,
+    @location(3) normal: vec3<f32>,
 ) -> VertexOutput {
     var result: VertexOutput;
     result.position = projection * view * model * vec4(position, 1.0);
     result.fragColor = color;
     result.tex_coord = tex_coord;
+    result.normal = (model * vec4(normal, 0.0)).xyz;
+    return result;
+}
+
+@vertex
+fn vs_instanced(
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coord: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) instance_0: vec4<f32>,
+    @location(5) instance_1: vec4<f32>,
+    @location(6) instance_2: vec4<f32>,
+    @location(7) instance_3: vec4<f32>,
+) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_0, instance_1, instance_2, instance_3);
+    var result: VertexOutput;
+    result.position = projection * view * model * instance * vec4(position, 1.0);
+    result.fragColor = color;
+    result.tex_coord = tex_coord;
+    result.normal = (model * instance * vec4(normal, 0.0)).xyz;
     return result;
 }
 
@@ -43,6 +66,43 @@ fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
     return tex;
 }
 
+// Grid dimensions in cells, the number of cells between two grid lines and
+// the side of the checker squares in cells
+struct GridLines {
+    size: vec2<f32>,
+    spacing: f32,
+    checker_size: f32,
+};
+
+@group(2)
+@binding(1)
+var<uniform> grid_lines: GridLines;
+
+// Drawn over the sand quad, keeps only the pixels within half a pixel of a grid line
+@fragment
+fn fs_grid(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let line = vertex.tex_coord * grid_lines.size / grid_lines.spacing;
+    let distance = abs(fract(line + 0.5) - 0.5) / fwidth(line);
+    if min(distance.x, distance.y) > 0.5 {
+        discard;
+    }
+    return vec4<f32>(1.0, 1.0, 1.0, 0.35);
+}
+
+// Drawn under the sand quad so empty cells show a checkerboard
+@fragment
+fn fs_checker(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let square = floor(vertex.tex_coord * grid_lines.size / grid_lines.checker_size);
+    let dark = (i32(square.x) + i32(square.y)) % 2 == 0;
+    return select(vec4<f32>(0.6, 0.6, 0.6, 1.0), vec4<f32>(0.4, 0.4, 0.4, 1.0), dark);
+}
+
+// Untextured, the vertex color is the final color
+@fragment
+fn fs_color(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vertex.fragColor, 1.0);
+}
+
 @fragment
 fn fs_wire(vertex: VertexOutput) -> @location(0) vec4<f32> {
     return vec4<f32>(0.0, 0.5, 0.0, 0.5);
@@ -0,0 +1,156 @@
+// What a cell does when the falling sweep of a step reaches it. Every material
+// has a built in behavior, `SandGrid::set_behavior` replaces it with any
+// `ParticleBehavior` so new ways of moving can be added without touching the
+// simulation loop:
+//
+//   struct Sticky;
+//   impl ParticleBehavior for Sticky {
+//       fn update(&self, ctx: &mut CellContext) {
+//           //only falls with nothing around to cling to
+//           if [(-1, 0), (1, 0)].iter().all(|&(dx, dy)| ctx.neighbour(dx, dy) == Some(Material::Empty)) {
+//               ctx.fall();
+//           }
+//       }
+//   }
+//   grid.set_behavior(Material::Sand, Arc::new(Sticky));
+//
+// Rising cells and cells launched against gravity are moved in another sweep
+// and keep their built in behavior.
+
+use glam::Vec2;
+
+use crate::material::Material;
+use crate::sand_grid::SandGrid;
+
+// Behaviors run on the worker threads of `simulate_parallel`, each band of the
+// grid shares them
+pub trait ParticleBehavior: Send + Sync {
+    fn update(&self, ctx: &mut CellContext);
+}
+
+// The cell a behavior updates and its surroundings. Offsets are relative to the
+// cell, with y pointing down, and wrap around the edges of a wrapping grid.
+// Frozen cells are never changed through it.
+pub struct CellContext<'a> {
+    grid: &'a mut SandGrid,
+    x: usize,
+    y: usize,
+    dt: f32,
+    travel: f32,
+}
+
+impl<'a> CellContext<'a> {
+    pub(crate) fn new(grid: &'a mut SandGrid, x: usize, y: usize, dt: f32, travel: f32) -> Self {
+        Self { grid, x, y, dt, travel }
+    }
+
+    // Where the cell is now, it changes when the cell swaps with another one
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn material(&self) -> Material {
+        self.grid.material_at(self.x, self.y).unwrap_or(Material::Empty)
+    }
+
+    // Length of the step in seconds
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    // Acceleration of the cell, gravity plus the pull of the attractors
+    pub fn accel(&self) -> Vec2 {
+        self.grid.force_at(self.x, self.y)
+    }
+
+    // Material of the cell (dx, dy) away, None past a solid edge
+    pub fn neighbour(&self, dx: isize, dy: isize) -> Option<Material> {
+        let (x, y) = self.grid.offset(self.x, self.y, dx, dy)?;
+        self.grid.material_at(x, y)
+    }
+
+    // Whether the cell (dx, dy) away is empty and not frozen
+    pub fn is_free(&self, dx: isize, dy: isize) -> bool {
+        self.grid.offset(self.x, self.y, dx, dy).is_some_and(|(x, y)| self.grid.material_at(x, y) == Some(Material::Empty) && !self.grid.is_frozen(x, y))
+    }
+
+    // Swaps the cell with the one (dx, dy) away, returns false without changing
+    // anything when that one is past a solid edge or frozen. A cell moved
+    // against gravity may be reached again later in the same sweep.
+    pub fn swap(&mut self, dx: isize, dy: isize) -> bool {
+        let Some((x, y)) = self.grid.offset(self.x, self.y, dx, dy) else {
+            return false;
+        };
+        if self.grid.is_frozen(x, y) {
+            return false;
+        }
+        self.grid.move_cell_to(self.x, self.y, x, y);
+        (self.x, self.y) = (x, y);
+        true
+    }
+
+    // Runs the first reaction with a neighbour, see `SandGrid::add_reaction`.
+    // Returns true when the cell changed, it shouldn't move any further then.
+    pub fn react(&mut self) -> bool {
+        self.grid.react(self.x, self.y)
+    }
+
+    // Falls along its acceleration, piling up and sliding down slopes. Where the
+    // cell ends up isn't tracked, this and `flow` are the last thing to call.
+    pub fn fall(&mut self) {
+        self.move_cell(false);
+    }
+
+    // Falls along its acceleration and spreads sideways when blocked
+    pub fn flow(&mut self) {
+        self.move_cell(true);
+    }
+
+    fn move_cell(&mut self, flows: bool) {
+        let accel = self.accel();
+        self.grid.move_cell(self.x, self.y, self.dt, accel, self.travel, flows);
+    }
+}
+
+// Grains like sand: react, otherwise fall and pile up
+pub struct SandBehavior;
+
+impl ParticleBehavior for SandBehavior {
+    fn update(&self, ctx: &mut CellContext) {
+        if !ctx.react() {
+            ctx.fall();
+        }
+    }
+}
+
+// Liquids and gases like water: react, otherwise fall and spread out. Liquids
+// are also leveled once everything fell, whatever their behavior.
+pub struct WaterBehavior;
+
+impl ParticleBehavior for WaterBehavior {
+    fn update(&self, ctx: &mut CellContext) {
+        if !ctx.react() {
+            ctx.flow();
+        }
+    }
+}
+
+// Walls like stone: never move but still react
+pub struct StaticBehavior;
+
+impl ParticleBehavior for StaticBehavior {
+    fn update(&self, ctx: &mut CellContext) {
+        ctx.react();
+    }
+}
+
+// Behavior of `material` unless `SandGrid::set_behavior` replaced it
+pub fn builtin(material: Material) -> &'static dyn ParticleBehavior {
+    if material.is_static() {
+        &StaticBehavior
+    } else if material.is_liquid() || material.is_gas() {
+        &WaterBehavior
+    } else {
+        &SandBehavior
+    }
+}
@@ -0,0 +1,206 @@
+pub struct CpuTexture {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl CpuTexture {
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Self {
+        Self { width, height, data }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, r:u8, g:u8, b:u8, a:u8) {
+        let i = (y * self.width  + x) * 4;
+        self.data[i] = r;
+        self.data[i + 1] = g;
+        self.data[i + 2] = b;
+        self.data[i + 3] = a;
+    }
+
+    pub fn get_pixels(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn get_pixel(&self,x: usize, y: usize) -> (u8,u8,u8,u8) {
+        let i = (y * self.width  + x) * 4;
+        let r = self.data[i];
+        let g = self.data[i + 1];
+        let b = self.data[i + 2];
+        let a = self.data[i + 3];
+        (r,g,b,a)
+    }
+
+    // Reads an 8 bit PNG, expanded to RGBA like `decode_png` does
+    pub fn load_png(path: impl AsRef<std::path::Path>) -> anyhow::Result<CpuTexture> {
+        let path = path.as_ref().to_string_lossy();
+        let (width, height, data) = decode_png(&path)?;
+        let (width, height) = (width as usize, height as usize);
+        if width == 0 || height == 0 {
+            anyhow::bail!("{path} is an empty {width}x{height} image");
+        }
+        if data.len() != width * height * 4 {
+            anyhow::bail!("{path} decoded to {} bytes, expected {} for {width}x{height} RGBA pixels", data.len(), width * height * 4);
+        }
+        Ok(Self::new(width, height, data))
+    }
+
+    // Writes the texture as an 8 bit RGBA PNG, `load_png` reads it back unchanged
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+
+    // Copies the pixels of `src` with its top left corner at (dst_x, dst_y),
+    // overwriting these ones. The offset may be negative, whatever falls
+    // outside of this texture is clipped.
+    pub fn blit(&mut self, src: &CpuTexture, dst_x: isize, dst_y: isize) {
+        //first source column and row that land inside
+        let src_x0 = dst_x.min(0).unsigned_abs();
+        let src_y0 = dst_y.min(0).unsigned_abs();
+        let x0 = dst_x.max(0) as usize;
+        let y0 = dst_y.max(0) as usize;
+        if src_x0 >= src.width || src_y0 >= src.height || x0 >= self.width || y0 >= self.height {
+            return;
+        }
+        let width = std::cmp::min(src.width - src_x0, self.width - x0);
+        let height = std::cmp::min(src.height - src_y0, self.height - y0);
+
+        for row in 0..height {
+            let from = ((src_y0 + row) * src.width + src_x0) * 4;
+            let to = ((y0 + row) * self.width + x0) * 4;
+            self.data[to..to + width * 4].copy_from_slice(&src.data[from..from + width * 4]);
+        }
+    }
+}
+
+// Linear RGBA colors as half floats, laid out for an Rgba16Float texture. Values
+// go above 1.0 for bright cells, a tonemapping pass brings them back on screen.
+pub struct HdrTexture {
+    width: usize,
+    height: usize,
+    data: Vec<u16>,
+}
+
+impl HdrTexture {
+    // Fully transparent width x height texture
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, data: vec![0; width * height * 4] }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgba: [f32; 4]) {
+        let i = (y * self.width + x) * 4;
+        for (channel, value) in self.data[i..i + 4].iter_mut().zip(rgba) {
+            *channel = f32_to_f16(value);
+        }
+    }
+
+    // The pixels as the bytes to upload
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::cast_slice(&self.data)
+    }
+}
+
+// Bits of the closest half float, values too large for it saturate at its
+// maximum (65504) and the ones too small to be normal flush to zero
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    //round half up on the 13 dropped mantissa bits
+    let mantissa = (bits & 0x7f_ffff) + 0x1000;
+    let (exponent, mantissa) = if mantissa & 0x80_0000 != 0 { (exponent + 1, 0) } else { (exponent, mantissa >> 13) };
+    if exponent >= 0x1f {
+        sign | 0x7bff
+    } else if exponent <= 0 {
+        sign
+    } else {
+        sign | ((exponent as u16) << 10) | mantissa as u16
+    }
+}
+
+// Decodes a PNG to RGBA8, palette, grayscale and RGB images are expanded
+pub fn decode_png(file_name: &str) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+    let image = std::fs::File::open(file_name)?;
+
+    let mut decoder = png::Decoder::new(image);
+    //palette to RGB, low bit depths to 8 bits and transparency chunks to an alpha channel
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    if info.bit_depth != png::BitDepth::Eight {
+        anyhow::bail!("{file_name} uses {:?} bits per channel, only 8 bit images are supported", info.bit_depth);
+    }
+
+    let pixels: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => anyhow::bail!("{file_name} is a palette image that could not be expanded"),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
+
+// Pixels of a fully transparent width x height texture
+pub fn new_texture(width:usize, height: usize) -> Vec<u8> {
+    new_texture_filled(width, height, (0, 0, 0, 0))
+}
+
+// Pixels of a width x height texture, all set to `rgba`
+pub fn new_texture_filled(width:usize, height: usize, rgba: (u8, u8, u8, u8)) -> Vec<u8> {
+    let (r, g, b, a) = rgba;
+    [r, g, b, a].repeat(width * height) //RGBA assumed
+}
+
+// Red to the right, green to the bottom debug pattern, looks like content so
+// it is only meant for testing the texture upload
+pub fn new_texture_gradient(width:usize, height: usize) -> Vec<u8> {
+    let mut res = new_texture(width, height);
+    for y in 0..height {
+        let v_ratio = y as f32 / height as f32;
+        let g = (v_ratio * 255.0).round() as u8;
+        for x in 0..width {
+            let i = (y*width + x) * 4;
+            let h_ratio = x as f32 / width as f32;
+
+            let r = (h_ratio * 255.0) as u8;
+            let b = 255_u8;
+            let a = 255_u8;
+
+            res[i] = r;
+            res[i + 1] = g;
+            res[i + 2] = b;
+            res[i + 3] = a;
+        }
+    }
+    res
+}
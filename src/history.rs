@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+use rust_sand_sim::sand_grid::{GridRegion, SandGrid};
+
+// Undo and redo stacks of paint strokes. A stroke is stored as the regions it
+// painted over, each copied right before it was painted, so undoing it only
+// touches the cells it changed.
+pub struct History {
+    undo: VecDeque<Vec<GridRegion>>,
+    redo: Vec<Vec<GridRegion>>,
+    stroke: Option<Vec<GridRegion>>, //stroke being painted, not undoable yet
+    capacity: usize, //oldest strokes are forgotten past it
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self { undo: VecDeque::new(), redo: Vec::new(), stroke: None, capacity }
+    }
+
+    // Adds the cells about to be painted over to the current stroke
+    pub fn record(&mut self, region: GridRegion) {
+        self.stroke.get_or_insert_with(Vec::new).push(region);
+    }
+
+    // Makes the current stroke undoable, a new stroke drops the redo stack
+    pub fn end_stroke(&mut self) {
+        let Some(stroke) = self.stroke.take() else {
+            return;
+        };
+        self.redo.clear();
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(stroke);
+    }
+
+    // Returns false when there is nothing to undo
+    pub fn undo(&mut self, grid: &mut SandGrid) -> bool {
+        self.end_stroke();
+        let Some(stroke) = self.undo.pop_back() else {
+            return false;
+        };
+        self.redo.push(Self::restore(grid, stroke));
+        true
+    }
+
+    // Returns false when there is nothing to redo
+    pub fn redo(&mut self, grid: &mut SandGrid) -> bool {
+        self.end_stroke();
+        let Some(stroke) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push_back(Self::restore(grid, stroke));
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.stroke = None;
+    }
+
+    // Pastes the regions back, latest first, and returns the cells they
+    // replaced in an order that restores them the same way
+    fn restore(grid: &mut SandGrid, stroke: Vec<GridRegion>) -> Vec<GridRegion> {
+        stroke
+            .iter()
+            .rev()
+            .map(|region| {
+                let replaced = grid.copy_region(region.x, region.y, region.width, region.height);
+                grid.paste_region(region);
+                replaced
+            })
+            .collect()
+    }
+}
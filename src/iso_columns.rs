@@ -0,0 +1,144 @@
+use glam::{Vec2, Vec3};
+use rust_sand_sim::material::Material;
+use rust_sand_sim::sand_grid::SandGrid;
+
+use crate::utils::Vertex;
+
+// Side of the cubes the columns are stacked from, in cells
+pub const TILE_SIZE: usize = 8;
+
+// Brightness of the faces facing the camera, the front and back ones and the
+// left and right ones, so the stacks read as 3D without lighting
+const FACE_SHADES: [f32; 3] = [1.0, 0.8, 0.6];
+
+// Pseudo 3D view of the grid: every TILE_SIZE wide column is drawn as a stack of
+// cubes rising from the bottom of the grid, as many as it holds cells for.
+// Rebuilt from the grid every frame it is shown, in the local space of the sand
+// quad so it follows the same transform.
+pub struct IsoColumns {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    capacity: usize, //cubes the buffers have room for
+}
+
+impl IsoColumns {
+    const VERTICES_PER_CUBE: usize = 20; //the face against the sand plane is never seen
+    const INDICES_PER_CUBE: usize = 30;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, 0);
+        Self { vertex_buffer, index_buffer, index_count: 0, capacity: 0 }
+    }
+
+    // Rebuilds the stacks from the cells of `grid` drawn on a quad of `quad_size`,
+    // cubes are `tile_depth` deep towards the camera
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, grid: &SandGrid, quad_size: Vec2, tile_depth: f32) {
+        let cell_size = quad_size / Vec2::new(grid.width as f32, grid.height as f32);
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for x0 in (0..grid.width).step_by(TILE_SIZE) {
+            let x1 = std::cmp::min(x0 + TILE_SIZE, grid.width);
+            let filled = (x0..x1)
+                .flat_map(|x| (0..grid.height).map(move |y| (x, y)))
+                .filter(|&(x, y)| Self::is_stacked(grid, x, y))
+                .count();
+            let tiles = std::cmp::min((filled as f32 / ((x1 - x0) * TILE_SIZE) as f32).round() as usize, grid.height.div_ceil(TILE_SIZE));
+            let column_color = Self::average_color(grid, x0..x1, 0..grid.height);
+
+            for tile in 0..tiles {
+                let y1 = grid.height.saturating_sub(tile * TILE_SIZE);
+                let y0 = y1.saturating_sub(TILE_SIZE);
+                let color = Self::average_color(grid, x0..x1, y0..y1).or(column_color).unwrap_or(Vec3::ONE);
+                let min = Vec3::new(x0 as f32 * cell_size.x, -tile_depth, y0 as f32 * cell_size.y);
+                let max = Vec3::new(x1 as f32 * cell_size.x, 0.0, y1 as f32 * cell_size.y);
+                Self::push_cube(&mut vertices, &mut indices, min, max, color);
+            }
+        }
+
+        let cubes = vertices.len() / Self::VERTICES_PER_CUBE;
+        if cubes > self.capacity {
+            //grows in steps so a rising pile doesn't reallocate every frame
+            self.capacity = std::cmp::max(cubes, self.capacity * 2);
+            (self.vertex_buffer, self.index_buffer) = Self::create_buffers(device, self.capacity);
+        }
+        self.index_count = indices.len() as u32;
+        if self.index_count > 0 {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+        }
+    }
+
+    // Draws the stacks with the bind groups already set on `rpass`
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass<'_>) {
+        if self.index_count == 0 {
+            return;
+        }
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+
+    // Cells that pile up, fire and gases only pass through
+    fn is_stacked(grid: &SandGrid, x: usize, y: usize) -> bool {
+        grid.material_at(x, y).is_some_and(|material| material != Material::Empty && !material.rises())
+    }
+
+    // Linear average of the colors of the stacked cells in the rectangle, None without any
+    fn average_color(grid: &SandGrid, columns: std::ops::Range<usize>, rows: std::ops::Range<usize>) -> Option<Vec3> {
+        let mut sum = Vec3::ZERO;
+        let mut count = 0;
+        for y in rows {
+            for x in columns.clone() {
+                if Self::is_stacked(grid, x, y) {
+                    let (r, g, b, _) = grid.color.get_pixel(x, y);
+                    //the texture bytes are sRGB, vertex colors are written out as linear
+                    let linear = |c: u8| (c as f32 / 255.0).powf(2.2);
+                    sum += Vec3::new(linear(r), linear(g), linear(b));
+                    count += 1;
+                }
+            }
+        }
+        (count > 0).then(|| sum / count as f32)
+    }
+
+    fn push_cube(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, min: Vec3, max: Vec3, color: Vec3) {
+        let [camera, front_back, sides] = FACE_SHADES;
+        //corners of each face, counter clockwise seen from outside
+        let faces = [
+            (Vec3::NEG_Y, camera, [Vec3::new(min.x, min.y, min.z), Vec3::new(max.x, min.y, min.z), Vec3::new(max.x, min.y, max.z), Vec3::new(min.x, min.y, max.z)]),
+            (Vec3::NEG_Z, front_back, [Vec3::new(min.x, min.y, min.z), Vec3::new(min.x, max.y, min.z), Vec3::new(max.x, max.y, min.z), Vec3::new(max.x, min.y, min.z)]),
+            (Vec3::Z, front_back, [Vec3::new(min.x, min.y, max.z), Vec3::new(max.x, min.y, max.z), Vec3::new(max.x, max.y, max.z), Vec3::new(min.x, max.y, max.z)]),
+            (Vec3::NEG_X, sides, [Vec3::new(min.x, min.y, min.z), Vec3::new(min.x, min.y, max.z), Vec3::new(min.x, max.y, max.z), Vec3::new(min.x, max.y, min.z)]),
+            (Vec3::X, sides, [Vec3::new(max.x, min.y, min.z), Vec3::new(max.x, max.y, min.z), Vec3::new(max.x, max.y, max.z), Vec3::new(max.x, min.y, max.z)]),
+        ];
+        for (normal, shade, corners) in faces {
+            let first = vertices.len() as u32;
+            for corner in corners {
+                let mut vertex = Vertex::new(corner, Vec2::ZERO);
+                vertex.color = color * shade;
+                vertex.normal = normal;
+                vertices.push(vertex);
+            }
+            indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
+    }
+
+    fn create_buffers(device: &wgpu::Device, cubes: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        //never empty, zero sized buffers can't be bound
+        let cubes = std::cmp::max(cubes, 1) as u64;
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Iso Columns Vertex Buffer"),
+            size: cubes * (Self::VERTICES_PER_CUBE * std::mem::size_of::<Vertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Iso Columns Index Buffer"),
+            size: cubes * (Self::INDICES_PER_CUBE * std::mem::size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (vertex_buffer, index_buffer)
+    }
+}
@@ -0,0 +1,35 @@
+// Falling sand simulation without any rendering, the winit/wgpu app in main.rs
+// is one frontend on top of it.
+//
+// A `SandGrid` holds the cells, `simulate` (or `simulate_parallel`) advances it
+// and its `color` texture is what to draw, `take_dirty` returns the part of it
+// changed since the last call:
+//
+//   let mut grid = SandGrid::new(width, height)?;
+//   grid.spawn_material_at(x, y, Material::Sand);
+//   grid.simulate(dt);
+//   if let Some(rect) = grid.take_dirty() {
+//       //upload `rect` of grid.color.get_pixels(), RGBA8 rows of grid.width pixels
+//   }
+//
+// `tick` advances it like `simulate` and also returns the cells that moved and
+// the reactions that happened during that step.
+//
+// `set_hdr` also keeps `hdr_color`, a linear half float copy of the colors with
+// glowing materials brighter than white, for a renderer tonemapping it.
+//
+// `set_behavior` swaps how cells of a material move for a `ParticleBehavior`,
+// see behavior.rs.
+//
+// `fill_rect`, `draw_line` and `spawn_column` build scenes from code, the same
+// way on every run. Everything else on `SandGrid` tunes the simulation (gravity,
+// wind, reactions, emitters, ...) or saves and loads grids.
+
+pub mod behavior;
+pub mod cpu_texture;
+pub mod material;
+pub mod sand_grid;
+
+pub use cpu_texture::CpuTexture;
+pub use material::Material;
+pub use sand_grid::SandGrid;
@@ -4,237 +4,1518 @@
 
 
 mod wgpu_app;
+mod history;
+mod iso_columns;
 mod model;
+mod overlay;
+mod presets;
 mod primitives;
+mod profiling;
+mod replay;
+mod snapshots;
 mod texture;
 mod utils;
 
-use anyhow::Result;
-use glam::{Mat4, UVec2, Vec2, Vec3};
+use glam::{Mat4, Vec3};
 use model::{Model, ModelDrawer};
-use primitives::CpuTexture;
-use rand::Rng;
+use rust_sand_sim::material::Material;
+use rust_sand_sim::cpu_texture::{self, CpuTexture};
+use rust_sand_sim::sand_grid::{self, Attractor, BoundaryMode, BrushShape, ColorMode, EdgeMode, Emitter, SandGrid};
 use utils::Vertex;
 use std::cell::RefCell;
 use std::{rc::Rc, sync::Arc};
 use std::mem::size_of;
+use wgpu::util::DeviceExt;
 use winit::window::Window;
 use winit_input_helper::WinitInputHelper;
 
+const SAVE_FILE_PATH: &str = "sand_grid.bin";
+//simulation resolution, the grid is stretched over the whole window whatever its size
+//and is never reallocated when the window is resized
+const GRID_WIDTH: usize = 512;
+const GRID_HEIGHT: usize = 512;
+const OVERLAY_WIDTH: usize = 128;
+const OVERLAY_HEIGHT: usize = 56;
+const OVERLAY_SCALE: f32 = 3.0;
+const REPOSE_THRESHOLDS: [f32; 3] = [0.0, 0.2, 0.9];
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 32.0;
+const WIND_STEP: f32 = 0.1;
+const MAX_WIND: f32 = 2.0;
+const PERSPECTIVE_TILT: f32 = 0.6; //angle between the perspective camera and the normal of the sand plane
+const ORBIT_SPEED: f32 = 1.5; //radians per second the arrow keys turn the perspective camera
+const MAX_ORBIT_TILT: f32 = 1.4; //kept short of a right angle so the camera never looks along its up axis
+const EMITTER_INTERVAL: u32 = 3; //ticks between two cells of an emitter
+const ATTRACTOR_STRENGTH: f32 = 2000.0;
+const DEFAULT_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const FRAME_THICKNESS: f32 = 8.0;
+const HISTORY_CAPACITY: usize = 64; //paint strokes that can be undone
+const DEFAULT_SNAPSHOT_COUNT: usize = 30; //about 6 MB each on a 512x512 grid
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1;
+const GRID_LINE_SPACING: f32 = 8.0; //cells between two debug grid lines
+const CHECKER_SIZE: f32 = 4.0; //side of the squares of the checker background in cells
+const MSAA_SAMPLE_COUNT: u32 = 4;
+const HDR_BLOOM_THRESHOLD: f32 = 1.0; //only glowing materials get brighter than white
+const LDR_BLOOM_THRESHOLD: f32 = 0.8;
+const BLOOM_INTENSITY: f32 = 0.8;
+const FIXED_DT: f32 = 1.0 / 60.0; //length of a simulation tick in seconds
+const MAX_TICKS_PER_FRAME: u32 = 5;
+const TIME_SCALES: [(winit::keyboard::KeyCode, f32); 3] = [
+    (winit::keyboard::KeyCode::F5, 0.25),
+    (winit::keyboard::KeyCode::F6, 1.0),
+    (winit::keyboard::KeyCode::F7, 4.0),
+];
+const OVERLAY_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+const DOUBLE_CLICK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(400);
+const SHADER_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+const DEFAULT_WINDOW_SIZE: (u32, u32) = (1280, 720);
+const MAX_WINDOW_DIMENSION: u32 = 16384;
+const MAX_BRUSH_SIZE: usize = 100; //largest square side or circle radius, in cells
+const BRUSH_PREVIEW_ALPHA: u8 = 96;
+
+// Command line, `--help` lists the options. Invalid values and unknown options
+// are reported before the window opens.
+#[derive(Clone, clap::Parser)]
+#[command(about = "Falling sand simulation")]
+struct CommandLine {
+    #[arg(value_name = "IMAGE", help = "PNG drawn with the material colors to start the grid from, the size of the grid")]
+    initial_image: Option<String>,
+    #[arg(long, help = "Window title")]
+    title: Option<String>,
+    //the other side is taken from DEFAULT_WINDOW_SIZE when only one is given
+    #[arg(long = "width", value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..=MAX_WINDOW_DIMENSION as i64), help = "Inner width of the window")]
+    window_width: Option<u32>,
+    #[arg(long = "height", value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..=MAX_WINDOW_DIMENSION as i64), help = "Inner height of the window")]
+    window_height: Option<u32>,
+    #[arg(long, value_name = "R,G,B[,A]", value_parser = Self::parse_color, help = "Clear color shown behind empty cells, components from 0 to 1")]
+    background: Option<[f32; 4]>,
+    #[arg(long, value_name = "FILE", help = "Writes the input of every frame to this file")]
+    record: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Feeds the input recorded in this file back instead of the live one")]
+    replay: Option<String>,
+    #[arg(long, value_name = "TICKS", help = "Pauses the simulation after this many ticks")]
+    max_steps: Option<u64>,
+    #[arg(long, visible_alias = "size", value_name = "WIDTHxHEIGHT", value_parser = Self::parse_size, help = format!("Cells of the grid, {GRID_WIDTH}x{GRID_HEIGHT} by default"))]
+    grid_size: Option<(usize, usize)>,
+    #[arg(long, value_name = "PIXELS", value_parser = Self::parse_sim_scale, help = "Window pixels per cell, sizes the grid from the window when there is no grid size")]
+    sim_scale: Option<usize>,
+    #[arg(long, value_name = "FILE", help = "WGSL file used instead of the embedded shaders/shader.wgsl, reloaded when it changes")]
+    shader: Option<String>,
+    #[arg(long, value_name = "COUNT", help = format!("Grid states kept to step back through while paused, {DEFAULT_SNAPSHOT_COUNT} by default"))]
+    snapshots: Option<usize>,
+    #[arg(long, value_name = "TICKS", value_parser = clap::value_parser!(u64).range(1..), help = format!("Ticks between two snapshots, {DEFAULT_SNAPSHOT_INTERVAL} by default"))]
+    snapshot_interval: Option<u64>,
+    #[arg(long, value_name = "OBJ", help = "OBJ file whose front outline is stamped into the starting grid as stone")]
+    stamp: Option<String>,
+    #[arg(long, value_name = "NAME", value_parser = Self::parse_preset, help = format!("Starting scene, one of {}", presets::names()))]
+    preset: Option<String>,
+}
+
+impl CommandLine {
+    const TITLE: &'static str = "My App"; //window title without --title
+
+    // Parsed on first use, the window is created from it before the app is.
+    // Exits with the usage on invalid arguments.
+    fn current() -> &'static CommandLine {
+        static COMMAND_LINE: std::sync::OnceLock<CommandLine> = std::sync::OnceLock::new();
+        COMMAND_LINE.get_or_init(<Self as clap::Parser>::parse)
+    }
+
+    // Both sides at least 1 cell
+    fn parse_size(size: &str) -> Result<(usize, usize), String> {
+        let parsed = size.split_once('x').and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)));
+        parsed.filter(|&(width, height)| width > 0 && height > 0).ok_or_else(|| format!("expected <width>x<height> of at least 1 cell each, got '{size}'"))
+    }
+
+    fn parse_sim_scale(scale: &str) -> Result<usize, String> {
+        scale.trim().parse().ok().filter(|scale| *scale > 0).ok_or_else(|| format!("expected at least 1 pixel per cell, got '{scale}'"))
+    }
+
+    fn parse_preset(name: &str) -> Result<String, String> {
+        match presets::find(name) {
+            Some(_) => Ok(name.to_string()),
+            None => Err(format!("expected one of {}", presets::names())),
+        }
+    }
+
+    // Inner size of the window, None leaves it to the OS
+    fn window_size(&self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        if self.window_width.is_none() && self.window_height.is_none() {
+            return None;
+        }
+        let (default_width, default_height) = DEFAULT_WINDOW_SIZE;
+        Some(winit::dpi::PhysicalSize::new(self.window_width.unwrap_or(default_width), self.window_height.unwrap_or(default_height)))
+    }
+
+    fn parse_color(color: &str) -> Result<[f32; 4], String> {
+        let components: Option<Vec<f32>> = color.split(',').map(|c| c.trim().parse().ok()).collect();
+        match components.as_deref() {
+            Some(&[r, g, b]) => Ok([r, g, b, 1.0]),
+            Some(&[r, g, b, a]) => Ok([r, g, b, a]),
+            _ => Err(format!("expected r,g,b or r,g,b,a, got '{color}'")),
+        }
+    }
+}
+
+// Snapshot shown in place of the grid while scrubbing, see `MyApp::scrub`
+struct Scrub {
+    live: SandGrid, //the grid the simulation carries on from once scrubbing ends
+    index: usize, //of the snapshot shown
+}
+
+// Shape the mouse paints and erases with. Tab cycles through them, - and =
+// shrink and grow the square and the circle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Brush {
+    Square { side: usize },
+    Circle { radius: usize },
+    Line, //from the cell a drag starts on to the one it ends on, see `MyApp::line_drag`
+}
+
+impl Brush {
+    const DEFAULT: Self = Brush::Square { side: 2 * SandGrid::BRUSH_RADIUS };
+
+    // Cells painted around the cursor, None for the line
+    fn shape(self) -> Option<BrushShape> {
+        match self {
+            Brush::Square { side } => Some(BrushShape::Square(side)),
+            Brush::Circle { radius } => Some(BrushShape::Circle(radius)),
+            Brush::Line => None,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Brush::Square { .. } => Brush::Circle { radius: SandGrid::BRUSH_RADIUS },
+            Brush::Circle { .. } => Brush::Line,
+            Brush::Line => Self::DEFAULT,
+        }
+    }
+
+    fn resized(self, grow: bool) -> Self {
+        let step = |size: usize, min: usize| if grow { std::cmp::min(size + 1, MAX_BRUSH_SIZE) } else { std::cmp::max(size.saturating_sub(1), min) };
+        match self {
+            Brush::Square { side } => Brush::Square { side: step(side, 1) },
+            Brush::Circle { radius } => Brush::Circle { radius: step(radius, 0) },
+            Brush::Line => Brush::Line,
+        }
+    }
+}
+
+// Cell whose readouts are shown on the overlay, see `MyApp::probe_lines`
+struct Probe {
+    x: usize,
+    y: usize,
+    passes: u32, //pass count of the cell at the last overlay refresh
+    flow: f32, //cells moved into it per second over the last refresh interval
+}
+
+struct Pipelines {
+    main: wgpu::RenderPipeline,
+    main_culled: wgpu::RenderPipeline, //`main` without back faces, for models with `cull_back_faces`
+    wire: Option<wgpu::RenderPipeline>, //None when the device doesn't support POLYGON_MODE_LINE
+    overlay: wgpu::RenderPipeline,
+    instanced: wgpu::RenderPipeline,
+    grid_lines: wgpu::RenderPipeline,
+    checker: wgpu::RenderPipeline,
+    iso: wgpu::RenderPipeline,
+}
+
+// What `MyApp::create_pipelines` builds the pipelines from
+struct PipelineInputs<'a> {
+    shader: &'a wgpu::ShaderModule,
+    layout: &'a wgpu::PipelineLayout,
+    grid_lines_layout: &'a wgpu::PipelineLayout,
+    vertex_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+    instanced_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+}
+
+// Shader file given with --shader, see `MyApp::poll_shader`
+struct ShaderWatch {
+    path: String,
+    modified: Option<std::time::SystemTime>, //of the version the pipelines were built from
+    checked: std::time::Instant, //last time `modified` was compared with the file
+}
+
+struct MsaaTargets {
+    color: wgpu::TextureView,
+    depth: wgpu::TextureView,
+}
+
+// Targets the scene is drawn into with linear colors when it is post processed
+// before reaching the surface. `color_bind_group` reads `color` back, the glow
+// is blurred back and forth between the two half size `bloom` textures.
+struct OffscreenTargets {
+    color: wgpu::TextureView,
+    depth: wgpu::TextureView,
+    color_bind_group: wgpu::BindGroup,
+    bloom: [wgpu::TextureView; 2],
+    bloom_bind_groups: [wgpu::BindGroup; 2],
+}
+
+// Passes of `MyApp::draw_bloom`, in order
+struct BloomPipelines {
+    extract: wgpu::RenderPipeline,
+    blur_horizontal: wgpu::RenderPipeline,
+    blur_vertical: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline, //adds the glow to the offscreen color target
+}
 
 struct MyApp {
     window: Arc<Window>,
     forward_depth: wgpu::TextureView,
-    pipeline: wgpu::RenderPipeline,
-    pipeline_wire: Option<wgpu::RenderPipeline>,
+    pipelines: Pipelines,
+    msaa_pipelines: Option<Pipelines>, //None when the surface format can't be multisampled
+    msaa_targets: Option<MsaaTargets>,
+    msaa: bool, //draws with `msaa_pipelines` into `msaa_targets`
+    hdr_pipelines: Pipelines, //draw the scene into `offscreen`
+    copy_pipeline: wgpu::RenderPipeline, //`offscreen` to the surface as it is
+    tonemap_pipeline: wgpu::RenderPipeline, //`offscreen` to the surface, tonemapped
+    bloom_pipelines: BloomPipelines,
+    bloom_buffer: wgpu::Buffer,
+    bloom_bind_group: wgpu::BindGroup, //threshold and intensity uniforms of the bloom passes
+    hdr: bool, //the sand quad shows the HDR colors of the grid, tonemapped so glowing materials get brighter than white
+    bloom: bool, //bright parts of the scene glow, see `draw_bloom`
+    offscreen: Option<OffscreenTargets>, //while `hdr` or `bloom` is on, matched to them at the start of `render`
+    ldr_sand_material: Option<model::Material>, //the sRGB material of the sand quad while it shows the HDR colors
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    grid_lines_pipeline_layout: wgpu::PipelineLayout,
+    surface_format: wgpu::TextureFormat,
+    shader_watch: Option<ShaderWatch>,
+    shader_reload_requested: bool, //done in `render`, which has the device
+    clear_color: bool, //false draws every frame over the previous one, see `color_load_op`
+    grid_lines_buffer: wgpu::Buffer,
+    grid_lines_bind_group: wgpu::BindGroup, //quad transform and grid line uniforms
+    iso_columns: iso_columns::IsoColumns,
+    iso_transform_bind_group: wgpu::BindGroup, //quad transform, the columns are built in its local space
+    show_iso_columns: bool, //stacks of cubes replace the flat sand quad
+    show_grid_lines: bool,
+    gpu_screenshot_requested: bool, //saved once the sand texture is uploaded in `render`
+    show_checker: bool, //empty cells show a checkerboard instead of the background color
     projection_buffer: wgpu::Buffer,
     projection_bindgroup: wgpu::BindGroup,
+    screen_projection_buffer: wgpu::Buffer,
+    screen_projection_bindgroup: wgpu::BindGroup,
+    viewport_size: glam::Vec2,
+    pan: glam::Vec2, //world position shown at the top left corner of the window
+    middle_press_position: Option<glam::Vec2>, //a middle press released without dragging places an emitter
+    freeze_drag_start: Option<(usize, usize)>, //cell an alt drag started on
+    brush: Brush,
+    line_start: Option<(usize, usize)>, //cell a drag with the line brush started on
+    brush_preview: CpuTexture, //grid sized, transparent but for the outline of the brush
+    brush_preview_cells: Vec<(usize, usize)>, //cells outlined in it
+    brush_preview_dirty: bool,
+    brush_preview_model: Model,
+    brush_preview_buffer: wgpu::Buffer,
+    brush_preview_bind_group: wgpu::BindGroup,
+    margin_click: Option<std::time::Instant>, //last left click outside of the grid, two close ones clear it
+    inspecting: bool, //left clicks pick the probed cell instead of painting
+    probe: Option<Probe>, //cell picked while inspecting, its readouts are shown on the overlay
+    zoom: f32,
+    projection_dirty: bool,
+    projection: Mat4,
+    camera: Mat4,
+    background: [f32; 4],
+    perspective: bool, //perspective camera tilted over the sand plane instead of the flat orthographic view
+    orbit_yaw: f32, //rotation of the perspective camera around the normal of the sand plane
+    orbit_tilt: f32, //angle between the perspective camera and the normal of the sand plane
+    quad_size: glam::Vec2,
     camera_buffer: wgpu::Buffer,
     camera_bindgroup: wgpu::BindGroup,
+    screen_camera_bindgroup: wgpu::BindGroup,
     sand_data: SandGrid,
-    quad_uniform_bind_group: wgpu::BindGroup,
+    quad_uniform_buffer: wgpu::Buffer,
+    quad_transform: Mat4,
     quad_model: Rc<RefCell<Model>>,
+    drawables: Vec<(Rc<RefCell<Model>>, wgpu::BindGroup)>, //models drawn by the main pipeline, with their transform
+    overlay: overlay::TextOverlay,
+    overlay_model: Model,
+    overlay_uniform_bind_group: wgpu::BindGroup,
+    show_overlay: bool,
+    overlay_dirty: bool,
+    overlay_frame_count: u32,
+    overlay_elapsed: std::time::Duration,
     frame_timer: utils::FrameTime,
     aspect_ratio: f32,
     show_wire: bool,
+    current_material: Material,
+    parallel_simulation: bool,
+    paused: bool, //painting still works, nothing is simulated, recorded or replayed
+    step_count: u64, //ticks simulated so far
+    tick_accumulator: f32, //frame time not simulated yet, less than FIXED_DT after each update
+    time_scale: f32, //simulated seconds per tick are FIXED_DT times this, below 1 is slow motion
+    pending_events: Vec<replay::GridEvent>, //input for the next tick
+    max_steps: Option<u64>,
     simulate_time: std::time::Duration,
-    texture_upload_time: std::time::Duration
+    texture_upload_time: std::time::Duration,
+    history: history::History, //undo and redo of paint strokes
+    snapshots: snapshots::Snapshots, //recent states of the grid, stepped through while paused
+    scrub: Option<Scrub>,
+    recorder: Option<replay::Recorder>,
+    replay: Option<replay::Replay>,
 }
 
-struct SandGrid {
-    width: usize,
-    height: usize,
-    meta: Vec<u8>, //occupied or not, but could be expanded in the future to include other metadata
-    color: CpuTexture,
-    velocity: Vec<Vec2>
-}
 
-impl SandGrid {
-    fn new(width: usize, height: usize) -> Self {
-        let mut meta = Vec::<u8>::with_capacity((width * height) as _);
-        meta.resize(meta.capacity(), 0);
-        let color = primitives::CpuTexture::new(
-            width as _,
-            height as _,
-            utils::new_texture(width as _, height as _));
-
-        let velocity = vec![Vec2::ZERO; (width * height) as _];
-
-        SandGrid {
-            width,
-            height,
-            meta,
-            color,
-            velocity
+
+impl MyApp {
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: size_of::<Vertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            wgpu::VertexAttribute { //pos
+                format: wgpu::VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            },
+            wgpu::VertexAttribute { //color
+                format: wgpu::VertexFormat::Float32x3,
+                offset: 4 * 3,
+                shader_location: 1,
+            },
+            wgpu::VertexAttribute { //uv
+                format: wgpu::VertexFormat::Float32x2,
+                offset: (4 * 3) + (4 * 3),
+                shader_location: 2,
+            },
+            wgpu::VertexAttribute { //normal
+                format: wgpu::VertexFormat::Float32x3,
+                offset: (4 * 3) + (4 * 3) + (4 * 2),
+                shader_location: 3,
+            },
+        ],
+    };
+    // The instance transform is fed as the four columns of a Mat4, in a second
+    // buffer next to the vertices
+    const INSTANCE_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: size_of::<Mat4>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 0,
+                shader_location: 4,
+            },
+            wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 4 * 4,
+                shader_location: 5,
+            },
+            wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 4 * 4 * 2,
+                shader_location: 6,
+            },
+            wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 4 * 4 * 3,
+                shader_location: 7,
+            },
+        ],
+    };
+    // Winding of front faces in every pipeline, the primitives are built with it
+    // and OBJ and glTF files use it too
+    const FRONT_FACE: wgpu::FrontFace = wgpu::FrontFace::Ccw;
+
+    // Every render pipeline of the app, drawing to `format` targets with `sample_count` samples
+    fn create_pipelines(device: &wgpu::Device, inputs: &PipelineInputs, format: wgpu::TextureFormat, sample_count: u32) -> Pipelines {
+        //blended so translucent cells like smoke show the background through them
+        let create_main = |cull_mode: Option<wgpu::Face>| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: inputs.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+        let pipeline = create_main(None);
+        let pipeline_culled = create_main(Some(wgpu::Face::Back));
+
+        let pipeline_instanced = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_instanced",
+                compilation_options: Default::default(),
+                buffers: inputs.instanced_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        let pipeline_overlay = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: inputs.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        //drawn over the sand quad at the same depth, so it passes on equal depth and doesn't write it
+        let pipeline_grid_lines = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.grid_lines_layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: inputs.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_grid",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        //drawn under the sand quad, which then passes the depth test against the same depth
+        let pipeline_checker = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.grid_lines_layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: inputs.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_checker",
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        //opaque vertex colored cubes, seen from every side as the camera moves
+        let pipeline_iso = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(inputs.layout),
+            vertex: wgpu::VertexState {
+                module: inputs.shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: inputs.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: inputs.shader,
+                entry_point: "fs_color",
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: Self::FRONT_FACE,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        let pipeline_wire = if device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            let pipeline_wire = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(inputs.layout),
+                vertex: wgpu::VertexState {
+                    module: inputs.shader,
+                    entry_point: "vs_main",
+                    compilation_options: Default::default(),
+                    buffers: inputs.vertex_buffers,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: inputs.shader,
+                    entry_point: "fs_wire",
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                operation: wgpu::BlendOperation::Add,
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            },
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    front_face: Self::FRONT_FACE,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Self::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+                multiview: None,
+                cache: None,
+            });
+            Some(pipeline_wire)
+        } else {
+            None
+        };
+
+        Pipelines {
+            main: pipeline,
+            main_culled: pipeline_culled,
+            wire: pipeline_wire,
+            overlay: pipeline_overlay,
+            instanced: pipeline_instanced,
+            grid_lines: pipeline_grid_lines,
+            checker: pipeline_checker,
+            iso: pipeline_iso,
+        }
+    }
+
+    // The regular pipelines, their multisampled copies when `msaa` and the HDR
+    // ones for post processing, which draw without MSAA
+    fn create_scene_pipelines(device: &wgpu::Device, inputs: &PipelineInputs, format: wgpu::TextureFormat, msaa: bool) -> (Pipelines, Option<Pipelines>, Pipelines) {
+        (
+            Self::create_pipelines(device, inputs, format, 1),
+            msaa.then(|| Self::create_pipelines(device, inputs, format, MSAA_SAMPLE_COUNT)),
+            Self::create_pipelines(device, inputs, Self::HDR_FORMAT, 1),
+        )
+    }
+
+    // Same as `create_scene_pipelines` with the shader read from the WGSL file at
+    // `path`, fails with the compile errors rather than panicking on them
+    fn compile_shader_file(
+        device: &wgpu::Device,
+        path: &str,
+        layout: &wgpu::PipelineLayout,
+        grid_lines_layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        msaa: bool,
+    ) -> anyhow::Result<(Pipelines, Option<Pipelines>, Pipelines)> {
+        let source = std::fs::read_to_string(path)?;
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(path),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let inputs = PipelineInputs {
+            shader: &shader,
+            layout,
+            grid_lines_layout,
+            vertex_buffers: &[Self::VERTEX_BUFFER_LAYOUT],
+            instanced_buffers: &[Self::VERTEX_BUFFER_LAYOUT, Self::INSTANCE_BUFFER_LAYOUT],
+        };
+        let pipelines = Self::create_scene_pipelines(device, &inputs, format, msaa);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            anyhow::bail!("{error}");
+        }
+        Ok(pipelines)
+    }
+
+    // Compiles the shader file given with --shader again when asked to or when
+    // it changed on disk. The pipelines are only replaced if it compiles.
+    fn poll_shader(&mut self, device: &wgpu::Device) {
+        let Some(watch) = self.shader_watch.as_mut() else {
+            return;
+        };
+        let requested = std::mem::take(&mut self.shader_reload_requested);
+        if !requested && watch.checked.elapsed() < SHADER_POLL_INTERVAL {
+            return;
+        }
+        watch.checked = std::time::Instant::now();
+        let modified = std::fs::metadata(&watch.path).and_then(|metadata| metadata.modified()).ok();
+        if !requested && modified == watch.modified {
+            return;
+        }
+        watch.modified = modified;
+
+        let msaa = self.msaa_pipelines.is_some();
+        match Self::compile_shader_file(device, &watch.path, &self.pipeline_layout, &self.grid_lines_pipeline_layout, self.surface_format, msaa) {
+            Ok((pipelines, msaa_pipelines, hdr_pipelines)) => {
+                self.pipelines = pipelines;
+                self.msaa_pipelines = msaa_pipelines;
+                self.hdr_pipelines = hdr_pipelines;
+                log::info!("Reloaded the shader from {}", watch.path);
+            }
+            Err(e) => log::error!("Unable to reload the shader from {}, keeping the current one: {e:#}", watch.path),
+        }
+    }
+
+    fn create_depth_texture(
+        config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+        sample_count: u32,
+    ) -> wgpu::TextureView {
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: None,
+            view_formats: &[],
+        });
+
+        depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    // Multisampled color and depth targets, the color one is resolved to the surface
+    fn create_msaa_targets(config: &wgpu::SurfaceConfiguration, device: &wgpu::Device) -> MsaaTargets {
+        let color_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: MSAA_SAMPLE_COUNT,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.view_formats[0],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("msaa color"),
+            view_formats: &[],
+        });
+        MsaaTargets {
+            color: color_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            depth: Self::create_depth_texture(config, device, MSAA_SAMPLE_COUNT),
+        }
+    }
+
+    // Draws a triangle covering the whole target with the `entry_point` fragment
+    // shader, for the post processing passes
+    fn create_fullscreen_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        entry_point: &str,
+        target: wgpu::ColorTargetState,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                compilation_options: Default::default(),
+                targets: &[Some(target)],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_offscreen_targets(width: u32, height: u32, device: &wgpu::Device, texture_bind_group_layout: &wgpu::BindGroupLayout) -> OffscreenTargets {
+        let create_view = |label: &str, width: u32, height: u32, format: wgpu::TextureFormat, usage: wgpu::TextureUsages| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                label: Some(label),
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let sampled = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let color = create_view("offscreen color", width, height, Self::HDR_FORMAT, sampled);
+        let depth = create_view("offscreen depth", width, height, Self::DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let (bloom_width, bloom_height) = (std::cmp::max(width / 2, 1), std::cmp::max(height / 2, 1));
+        let bloom = [
+            create_view("bloom", bloom_width, bloom_height, Self::HDR_FORMAT, sampled),
+            create_view("bloom", bloom_width, bloom_height, Self::HDR_FORMAT, sampled),
+        ];
+
+        //linear so the half size glow is smooth once stretched back over the scene
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let create_bind_group = |view: &wgpu::TextureView| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: None,
+        });
+        OffscreenTargets {
+            color_bind_group: create_bind_group(&color),
+            bloom_bind_groups: [create_bind_group(&bloom[0]), create_bind_group(&bloom[1])],
+            color,
+            depth,
+            bloom,
+        }
+    }
+
+    // Nearest filtering keeps single grains crisp when zoomed in, mips avoid
+    // shimmering when zoomed out
+    fn sand_texture_options(format: wgpu::TextureFormat) -> texture::TextureOptions {
+        texture::TextureOptions { filter: wgpu::FilterMode::Nearest, mipmaps: true, format }
+    }
+
+    // Matches the sand texture and the offscreen targets to `hdr` and `bloom`
+    // after they changed. The sand quad draws the HDR colors of the grid while
+    // `hdr` is on.
+    fn sync_post_processing(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.hdr != self.ldr_sand_material.is_some() {
+            self.sand_data.set_hdr(self.hdr);
+            let mut quad_model = self.quad_model.borrow_mut();
+            match (self.ldr_sand_material.take(), self.sand_data.hdr_color()) {
+                (None, Some(hdr)) => {
+                    let options = Self::sand_texture_options(Self::HDR_FORMAT);
+                    let texture = texture::Texture::from_bytes_with_options(device, queue, hdr.get_width(), hdr.get_height(), hdr.as_bytes(), &options)
+                        .expect("Unable to create the HDR sand texture");
+                    let material = model::Material::new(device, "HDR Sand Material", texture, &self.texture_bind_group_layout);
+                    self.ldr_sand_material = Some(std::mem::replace(&mut quad_model.materials[0], material));
+                }
+                (Some(ldr_sand_material), _) => quad_model.materials[0] = ldr_sand_material,
+                (None, None) => {}
+            }
+        }
+
+        let post_processed = self.hdr || self.bloom;
+        if post_processed != self.offscreen.is_some() {
+            self.offscreen = post_processed.then(|| {
+                Self::create_offscreen_targets(self.viewport_size.x as u32, self.viewport_size.y as u32, device, &self.texture_bind_group_layout)
+            });
+        }
+    }
+
+    // Uniforms of the bloom passes: the threshold, the intensity and padding.
+    // The HDR colors are only over 1.0 for glowing materials, without them
+    // anything close to white glows.
+    fn bloom_uniform(&self) -> [f32; 4] {
+        let threshold = if self.hdr { HDR_BLOOM_THRESHOLD } else { LDR_BLOOM_THRESHOLD };
+        [threshold, BLOOM_INTENSITY, 0.0, 0.0]
+    }
+
+    // Adds the blurred parts of the offscreen color target brighter than the
+    // bloom threshold back on top of it
+    fn draw_bloom(&self, encoder: &mut wgpu::CommandEncoder, offscreen: &OffscreenTargets) {
+        let pipelines = &self.bloom_pipelines;
+        let passes = [
+            (&pipelines.extract, &offscreen.color_bind_group, &offscreen.bloom[0], true),
+            (&pipelines.blur_horizontal, &offscreen.bloom_bind_groups[0], &offscreen.bloom[1], true),
+            (&pipelines.blur_vertical, &offscreen.bloom_bind_groups[1], &offscreen.bloom[0], true),
+            (&pipelines.composite, &offscreen.bloom_bind_groups[0], &offscreen.color, false),
+        ];
+        for (pipeline, source, target, clear) in passes {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: if clear { wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT) } else { wgpu::LoadOp::Load },
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, source, &[]);
+            rpass.set_bind_group(1, &self.bloom_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+    }
+
+    // Writes the sand color buffer to a timestamped PNG next to the executable
+    fn save_screenshot(&self) -> anyhow::Result<std::path::PathBuf> {
+        let path = Self::screenshot_path("screenshot")?;
+        self.sand_data.color.save_png(&path)?;
+        Ok(path)
+    }
+
+    // Same as `save_screenshot` from the sand texture read back from the GPU
+    // rather than its CPU copy, so both can be compared
+    fn save_gpu_screenshot(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<std::path::PathBuf> {
+        if self.ldr_sand_material.is_some() {
+            anyhow::bail!("GPU screenshots are 8 bit, turn HDR off first");
         }
+        let path = Self::screenshot_path("gpu_screenshot")?;
+        let mut quad_model = self.quad_model.borrow_mut();
+        let texture = &quad_model.get_material(0).diffuse_texture;
+        let pixels = texture.read_back(device, queue)?;
+        let size = texture.texture.size();
+        utils::save_png(&path, size.width, size.height, &pixels)?;
+        Ok(path)
     }
 
+    fn screenshot_path(prefix: &str) -> anyhow::Result<std::path::PathBuf> {
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis();
+        let exe = std::env::current_exe()?;
+        let dir = exe.parent().unwrap_or(std::path::Path::new("."));
+        Ok(dir.join(format!("{prefix}_{timestamp}.png")))
+    }
 
-    fn simulate(&mut self, dt: f32) {
-        const ACCEL: Vec2 = Vec2::new(0.0,9.81);
+    // Refreshes the overlay text every OVERLAY_REFRESH_INTERVAL with the average FPS
+    // over that interval and the timings of the last frame
+    fn update_overlay(&mut self, dt: std::time::Duration) {
+        self.overlay_frame_count += 1;
+        self.overlay_elapsed += dt;
+        if self.overlay_elapsed < OVERLAY_REFRESH_INTERVAL {
+            return;
+        }
 
-        for y in (0..self.height).rev() {
-            for x in 0..self.width {
-                let i_current = self.coord_to_index(x, y);
-                if !Self::is_pixel_solid(self.meta[i_current]) {
-                    continue;
-                }
-    
-                if y == self.height - 1 {
-                    continue;
-                }
+        let fps = self.overlay_frame_count as f32 / self.overlay_elapsed.as_secs_f32();
+        if let Some(probe) = self.probe.as_mut() {
+            let passes = self.sand_data.pass_count(probe.x, probe.y).unwrap_or(0);
+            probe.flow = passes.wrapping_sub(probe.passes) as f32 / self.overlay_elapsed.as_secs_f32();
+            probe.passes = passes;
+        }
+        self.overlay_frame_count = 0;
+        self.overlay_elapsed = std::time::Duration::new(0, 0);
+        if !self.show_overlay {
+            return;
+        }
 
-                let v = self.velocity[i_current];
-                let v_next = v + ACCEL * dt;
-                self.velocity[i_current] = v_next;
-                self.color.set_pixel(x, y, (v_next.y/10.0 * 255.0).round() as u8, (v_next.x/10.0 * 255.0).round() as u8, 0, 255);
+        let mut lines = vec![
+            format!("FPS {fps:.1}"),
+            format!("SIM {:.2}MS", self.simulate_time.as_secs_f32() * 1000.0),
+            format!("UPLOAD {:.2}MS", self.texture_upload_time.as_secs_f32() * 1000.0),
+        ];
+        lines.extend(self.probe_lines());
+        self.overlay.set_lines(&lines);
+        self.overlay_dirty = true;
+    }
 
-                if v_next.length_squared() < 1.0 {
-                    continue;
-                }
+    // Readouts of the probed cell: where it is and what it holds, the height of
+    // the pile in its column and the flow through it
+    fn probe_lines(&self) -> Vec<String> {
+        let Some(probe) = &self.probe else {
+            return Vec::new();
+        };
+        let material = self.sand_data.material_at(probe.x, probe.y).unwrap_or(Material::Empty);
+        vec![
+            format!("{},{} {}", probe.x, probe.y, material.name().to_uppercase()),
+            format!("HEIGHT {}", self.sand_data.column_height(probe.x)),
+            format!("FLOW {:.1}/S", probe.flow),
+        ]
+    }
 
-                let target_position = UVec2::new(
-                    std::cmp::min(x as u32 + v_next.x.round() as u32, (self.width - 1) as _), 
-                    std::cmp::min(y as u32 + v_next.y.round() as u32, (self.height - 1) as _)
-                );
-                let y_target = target_position.y as usize;
-                let mut y_target_collision = y+1;
-                //find the next collision
-                for y_bellow in y+1..y_target+1 {
-                    if y_bellow == self.height - 1 {
-                        break;
-                    }
+    // Projection and camera for the current mode, pan and zoom
+    fn view_matrices(&self) -> (Mat4, Mat4) {
+        if self.perspective {
+            //flipped vertically so the grid keeps the orientation of the orthographic view
+            let flip = Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0));
+            (flip * utils::create_perspective_matrix(self.viewport_size.x / self.viewport_size.y, 10000.0), self.perspective_camera())
+        } else {
+            let visible_size = self.viewport_size / self.zoom;
+            (utils::create_iso_view_matrix(self.pan, visible_size.x, visible_size.y), Self::ortho_camera())
+        }
+    }
 
-                    let i_bellow =  self.coord_to_index(x, y_bellow);
-                    if Self::is_pixel_solid(self.meta[i_bellow]) {
-                        break;
-                    }
-                    y_target_collision = y_bellow
-                }
-
-                let i_bellow =  self.coord_to_index(x, y_target_collision);
-    
-                let pixel_bellow = self.meta[i_bellow];
-                if !Self::is_pixel_solid(pixel_bellow) {
-                    self.swap_cell(x,y, x, y_target_collision);
-                } else {
-                    //TODO: remove the determinism of the sand always preferring to slide to the right first
-                    let i_pixel_bellow_r = self.coord_to_index(x + 1, y_target_collision);
-                    let i_pixel_bellow_l = self.coord_to_index(x - 1, y_target_collision);
-    
-                    if x - 1 >= 0 && !Self::is_pixel_solid(self.meta[i_pixel_bellow_l]) {
-                        self.swap_cell( x,y, x-1, y_target_collision);
-                    } else if x + 1 < self.width && !Self::is_pixel_solid(self.meta[i_pixel_bellow_r]) {
-                        self.swap_cell( x,y, x+1, y_target_collision);
-                    }
+    // Writes the projection and the camera for the current mode, pan and zoom
+    fn write_projection(&mut self, queue: &wgpu::Queue) {
+        (self.projection, self.camera) = self.view_matrices();
+        let mx_ref: &[f32; 16] = self.projection.as_ref();
+        queue.write_buffer(&self.projection_buffer, 0, bytemuck::cast_slice(mx_ref));
+        let mx_ref: &[f32; 16] = self.camera.as_ref();
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(mx_ref));
+        self.projection_dirty = false;
+    }
 
-                }
+    // Looks straight down the y axis at the sand plane
+    fn ortho_camera() -> Mat4 {
+        let cam_pos = glam::Vec3::new(0.0, -100.0, 0.0);
+        let cam_rot = glam::Quat::from_rotation_arc(glam::Vec3::new(0.0, 1.0, 0.0), (glam::Vec3::new(0.0, 0.0, 0.0) - cam_pos).normalize());
+        utils::get_view_matrix(cam_pos, cam_rot)
+    }
+
+    // Looks at the center of the view from orbit_tilt away from the normal of the
+    // sand plane, towards the bottom of the window until orbit_yaw turns it, and
+    // far enough for the whole window to fit at zoom 1
+    fn perspective_camera(&self) -> Mat4 {
+        let target = Vec3::new(self.viewport_size.x * 0.5 + self.pan.x, 0.0, self.viewport_size.y * 0.5 + self.pan.y);
+        let distance = self.viewport_size.y * 0.5 / std::f32::consts::FRAC_PI_8.tan() / self.zoom;
+        let forward = glam::Quat::from_rotation_y(self.orbit_yaw) * Vec3::new(0.0, self.orbit_tilt.cos(), -self.orbit_tilt.sin());
+        let cam_rot = glam::Quat::from_rotation_arc(glam::Vec3::new(0.0, 1.0, 0.0), forward);
+        utils::get_view_matrix(target - forward * distance, cam_rot)
+    }
+
+    // Middle drag pans the view, the scroll wheel zooms around the cursor and in
+    // perspective the arrow keys orbit the camera around the sand plane
+    fn update_camera(&mut self, input: &WinitInputHelper, dt: f32) {
+        if input.mouse_held(winit::event::MouseButton::Middle) {
+            let (dx, dy) = input.cursor_diff();
+            if dx != 0.0 || dy != 0.0 {
+                self.pan -= glam::Vec2::new(dx, dy) / self.zoom;
+                self.projection_dirty = true;
+            }
+        }
+
+        let (_, scroll) = input.scroll_diff();
+        if scroll != 0.0 {
+            let cursor = input.cursor().map_or(self.viewport_size * 0.5, |(x, y)| glam::Vec2::new(x, y));
+            let world_under_cursor = self.pan + cursor / self.zoom;
+            self.zoom = (self.zoom * 1.1_f32.powf(scroll)).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.pan = world_under_cursor - cursor / self.zoom;
+            self.projection_dirty = true;
+        }
 
+        if self.perspective {
+            let held = |key| if input.key_held(key) { 1.0 } else { 0.0 };
+            let yaw = held(winit::keyboard::KeyCode::ArrowRight) - held(winit::keyboard::KeyCode::ArrowLeft);
+            let tilt = held(winit::keyboard::KeyCode::ArrowDown) - held(winit::keyboard::KeyCode::ArrowUp);
+            if yaw != 0.0 || tilt != 0.0 {
+                self.orbit_yaw = (self.orbit_yaw + yaw * ORBIT_SPEED * dt).rem_euclid(std::f32::consts::TAU);
+                self.orbit_tilt = (self.orbit_tilt + tilt * ORBIT_SPEED * dt).clamp(0.0, MAX_ORBIT_TILT);
+                self.projection_dirty = true;
             }
         }
     }
 
-    fn coord_to_index(&self, x: usize, y: usize) -> usize {
-        y*self.width + x
+    // Maps a window position to the grid cell under it by intersecting the view ray
+    // with the plane of the sand quad (y = 0), None when the cursor is off the grid,
+    // in the margin around it or outside of the window. Uses the view as it is
+    // now, not as last written, so a pan or zoom earlier in the frame is accounted for.
+    fn screen_to_grid(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        if self.viewport_size.x <= 0.0 || self.viewport_size.y <= 0.0 {
+            return None;
+        }
+        if !(0.0..self.viewport_size.x).contains(&x) || !(0.0..self.viewport_size.y).contains(&y) {
+            return None;
+        }
+        let ndc_x = 2.0 * x / self.viewport_size.x - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / self.viewport_size.y;
+
+        let (projection, camera) = self.view_matrices();
+        let clip_to_world = (projection * camera).inverse();
+        let near = clip_to_world.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = clip_to_world.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+        let ray = far - near;
+        if ray.y.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = -near.y / ray.y;
+        if distance < 0.0 {
+            //the plane is behind the camera
+            return None;
+        }
+        let hit = near + ray * distance;
+        let local_hit = self.quad_transform.inverse().transform_point3(hit);
+
+        let u = local_hit.x / self.quad_size.x;
+        let v = local_hit.z / self.quad_size.y;
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+
+        let grid_x = std::cmp::min((u * self.sand_data.width as f32) as usize, self.sand_data.width - 1);
+        let grid_y = std::cmp::min((v * self.sand_data.height as f32) as usize, self.sand_data.height - 1);
+        Some((grid_x, grid_y))
+    }
+
+    // Seeds the grid from a PNG drawn with the material colors, it has to match the grid size
+    fn initial_grid_from_image(path: &str, width: usize, height: usize) -> anyhow::Result<SandGrid> {
+        let grid = SandGrid::from_image(path, |color| Some(Material::closest_to_color(color)))?;
+        if grid.width != width || grid.height != height {
+            anyhow::bail!("{path} is {}x{} but the grid is {width}x{height}", grid.width, grid.height);
+        }
+        log::info!("Loaded the initial grid from {path}");
+        Ok(grid)
     }
 
-        
-    fn spawn_sand_at(&mut self,x: usize, y: usize) {
-        let mut rng = rand::thread_rng();
+    // Empty grid of the requested size, the default one when it can't be created
+    // or its texture would be larger than the device supports
+    fn empty_grid(width: usize, height: usize, max_texture_size: usize) -> SandGrid {
+        let grid = if width > max_texture_size || height > max_texture_size {
+            Err(anyhow::anyhow!("textures are at most {max_texture_size}x{max_texture_size} on this device"))
+        } else {
+            SandGrid::new(width, height)
+        };
+        grid.unwrap_or_else(|e| {
+            log::error!("Unable to create a {width}x{height} grid, using {GRID_WIDTH}x{GRID_HEIGHT}: {e}");
+            SandGrid::new(GRID_WIDTH, GRID_HEIGHT).expect("the default grid size is valid")
+        })
+    }
+
+    // Places the quad, built `quad_size` big, at the largest size with the
+    // aspect ratio of the grid that fits the viewport, centered in it, so cells
+    // stay square whatever the shapes of the grid and the window
+    fn quad_fit_transform(viewport_size: glam::Vec2, quad_size: glam::Vec2, grid: &SandGrid) -> Mat4 {
+        let grid_size = glam::Vec2::new(grid.width as f32, grid.height as f32);
+        let fitted_size = grid_size * (viewport_size / grid_size).min_element();
+        let offset = (viewport_size - fitted_size) * 0.5;
+        let scale = fitted_size / quad_size;
+        Mat4::from_translation(Vec3::new(offset.x, 0.0, offset.y)) * Mat4::from_scale(Vec3::new(scale.x, 1.0, scale.y))
+    }
 
-        for y in y-10..y+10 {
-            if y >= self.height {
-                continue;
+    // Transform of the brush preview, the one of the sand quad lifted a little
+    // towards the camera so it is drawn over the cells
+    fn brush_preview_transform(quad_transform: Mat4) -> Mat4 {
+        Mat4::from_translation(Vec3::new(0.0, -0.5, 0.0)) * quad_transform
+    }
+
+    // Shows the snapshot before the one shown, or after it when `back` is false,
+    // in place of the grid. The first step back shows the latest snapshot taken
+    // before the current tick, stepping forward past the latest one goes back
+    // to the grid. Only the grid shown changes, the simulation carries on from
+    // where it was once scrubbing ends.
+    fn scrub(&mut self, back: bool) {
+        let index = match (&self.scrub, back) {
+            (None, true) => self.snapshots.latest_before(self.step_count),
+            (None, false) => None,
+            (Some(scrub), true) => scrub.index.checked_sub(1),
+            (Some(scrub), false) => Some(scrub.index + 1),
+        };
+        let Some((index, snapshot)) = index.and_then(|index| self.snapshots.get(index).map(|snapshot| (index, snapshot))) else {
+            if back {
+                log::info!("No older snapshot");
+            } else {
+                self.stop_scrubbing();
             }
-            for x in x-10..x+10 {
-                if x >= self.width {
-                    continue;
-                }
-                if rng.gen_bool(0.5) {
-                    continue;
-                }
-                let i = self.coord_to_index(x , y );
+            return;
+        };
+        let live = match self.scrub.take() {
+            Some(scrub) => scrub.live,
+            None => std::mem::replace(&mut self.sand_data, SandGrid::new(1, 1).expect("a 1x1 grid is valid")),
+        };
+        let mut grid = snapshot.to_grid(&live);
+        grid.set_hdr(self.hdr);
+        log::info!("Showing tick {} of {}", snapshot.tick, self.step_count);
+        self.sand_data = grid;
+        self.scrub = Some(Scrub { live, index });
+    }
+
+    // Puts the grid back in place of the snapshot shown, if any
+    fn stop_scrubbing(&mut self) {
+        if let Some(scrub) = self.scrub.take() {
+            self.sand_data = scrub.live;
+            self.sand_data.mark_all_dirty();
+            log::info!("Back to tick {}", self.step_count);
+        }
+    }
 
-                self.meta[i] = 1;
-                self.velocity[i] = Vec2::new(0.0, 1.0);
-                let r = 0 as u8;
-                let g = 255 as u8;
-                let b = 255 as u8;
-                let a = 255 as u8;
-                self.color.set_pixel(x, y, r, g, b, a);
+    // Simulates one tick of `dt` seconds, recorded or replayed along with its input
+    fn tick(&mut self, dt: f32, events: Vec<replay::GridEvent>) {
+        let frame = self.next_frame(dt, events);
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.write_frame(&frame) {
+                log::error!("Unable to record input, recording stopped: {e}");
+                self.recorder = None;
             }
         }
-        
+        self.apply_frame(&frame);
+
+        let timer = std::time::Instant::now();
+        {
+            profiling::span!("simulate");
+            if self.parallel_simulation {
+                self.sand_data.simulate_parallel(frame.dt);
+            } else {
+                self.sand_data.simulate(frame.dt);
+            }
+        }
+        self.simulate_time = timer.elapsed();
+        if log::log_enabled!(log::Level::Debug) {
+            log::debug!("Simulation stats: {:?}, kinetic energy {:.3}", self.sand_data.stats(), self.sand_data.total_kinetic_energy());
+        }
+
+        self.step_count += 1;
+        self.snapshots.capture(self.step_count, &self.sand_data);
+        if self.max_steps == Some(self.step_count) {
+            self.paused = true;
+            log::info!("Reached {} steps, paused, grid hash {:016x}", self.step_count, self.sand_data.state_hash());
+        }
     }
 
-    fn is_pixel_solid(info:u8) -> bool {
-        info!=0
+    // The recorded frame while replaying, otherwise the live one
+    fn next_frame(&mut self, dt: f32, events: Vec<replay::GridEvent>) -> replay::FrameRecord {
+        if let Some(replay) = self.replay.as_mut() {
+            if let Some(frame) = replay.next_frame() {
+                return frame;
+            }
+            log::info!("Replay finished, grid hash {:016x}", self.sand_data.state_hash());
+            self.replay = None;
+        }
+
+        replay::FrameRecord {
+            dt,
+            settings: replay::FrameSettings {
+                gravity: self.sand_data.gravity,
+                wind: self.sand_data.wind,
+                repose_threshold: self.sand_data.repose_threshold,
+                heat_enabled: self.sand_data.heat_enabled,
+                parallel_simulation: self.parallel_simulation,
+                boundary: self.sand_data.boundary,
+            },
+            events,
+        }
     }
 
-    fn swap_cell(&mut self, x: usize, y: usize, x1: usize, y1: usize) {
-        let i = y*self.width + x;
-        let i1 = y1*self.width + x1;
+    fn apply_frame(&mut self, frame: &replay::FrameRecord) {
+        let settings = &frame.settings;
+        self.sand_data.gravity = settings.gravity;
+        self.sand_data.wind = settings.wind;
+        self.sand_data.repose_threshold = settings.repose_threshold;
+        self.sand_data.heat_enabled = settings.heat_enabled;
+        self.parallel_simulation = settings.parallel_simulation;
+        self.sand_data.boundary = settings.boundary;
+        self.apply_events(&frame.events);
+    }
 
-        //swap sand info data
-        let t = self.meta[i1];
-        self.meta[i1] = self.meta[i];
-        self.meta[i] = t;
+    fn apply_events(&mut self, events: &[replay::GridEvent]) {
+        //a stroke lasts as long as every frame paints or erases something
+        let painting = events.iter().any(|event| matches!(event, replay::GridEvent::Spawn { .. } | replay::GridEvent::Erase { .. }));
+        if !painting {
+            self.history.end_stroke();
+        }
 
-        //swap color data
-        let pixel = self.color.get_pixel(x, y);
-        let pixel1 = self.color.get_pixel(x1, y1);
-        let (r,g,b,a) = pixel;
-        self.color.set_pixel(x1, y1, r, g, b, a);
-        let (r,g,b,a) = pixel1;
-        self.color.set_pixel(x, y, r, g, b, a);
+        for event in events {
+            match *event {
+                replay::GridEvent::Spawn { x, y, material, shape } => {
+                    self.record_brush(x, y, shape);
+                    self.sand_data.spawn_shape_at(x, y, material, shape);
+                }
+                replay::GridEvent::Erase { x, y, shape } => {
+                    self.record_brush(x, y, shape);
+                    self.sand_data.erase_shape_at(x, y, shape);
+                }
+                replay::GridEvent::Line { from, to, material } => {
+                    //a stroke of its own, like a fill
+                    self.history.end_stroke();
+                    let (x, y) = (std::cmp::min(from.0, to.0), std::cmp::min(from.1, to.1));
+                    self.history.record(self.sand_data.copy_region(x, y, from.0.abs_diff(to.0) + 1, from.1.abs_diff(to.1) + 1));
+                    self.history.end_stroke();
+                    self.sand_data.draw_line((from.0 as isize, from.1 as isize), (to.0 as isize, to.1 as isize), material);
+                }
+                replay::GridEvent::Fill { x, y, material } => {
+                    //a fill can reach any cell, it is a stroke of its own covering the whole grid
+                    self.history.end_stroke();
+                    self.history.record(self.sand_data.copy_region(0, 0, self.sand_data.width, self.sand_data.height));
+                    self.history.end_stroke();
+                    self.sand_data.flood_fill(x, y, material);
+                }
+                replay::GridEvent::Clear => {
+                    //undoable like a fill, as a stroke covering the whole grid
+                    self.history.end_stroke();
+                    self.history.record(self.sand_data.copy_region(0, 0, self.sand_data.width, self.sand_data.height));
+                    self.history.end_stroke();
+                    self.sand_data.clear();
+                    log::info!("Cleared the grid");
+                }
+                replay::GridEvent::Undo => {
+                    if !self.history.undo(&mut self.sand_data) {
+                        log::info!("Nothing to undo");
+                    }
+                }
+                replay::GridEvent::Redo => {
+                    if !self.history.redo(&mut self.sand_data) {
+                        log::info!("Nothing to redo");
+                    }
+                }
+                replay::GridEvent::Emitter { x, y, material } => {
+                    self.sand_data.emitters.push(Emitter::new(x, y, material, EMITTER_INTERVAL));
+                    log::info!("Placed a {} emitter at {x}, {y}", material.name());
+                }
+                replay::GridEvent::Attractor { x, y } => {
+                    self.sand_data.attractors.push(Attractor { x: x as f32, y: y as f32, strength: ATTRACTOR_STRENGTH });
+                    log::info!("Placed an attractor at {x}, {y}");
+                }
+                replay::GridEvent::Freeze { x, y, width, height, frozen } => {
+                    self.sand_data.set_frozen(x, y, width, height, frozen);
+                    log::info!("{} {width}x{height} cells at {x}, {y}", if frozen { "Froze" } else { "Thawed" });
+                }
+                replay::GridEvent::ClearAttractors => {
+                    self.sand_data.attractors.clear();
+                    log::info!("Removed all attractors");
+                }
+            }
+        }
+    }
 
-        //swap velocity data
-        let v = self.velocity[i1];
-        self.velocity[i1] = self.velocity[i];
-        self.velocity[i] = v;
+    // Alt drag spans a rectangle from the cell it starts on to the one it ends
+    // on, freezing it with the left button and thawing it with the right one
+    fn freeze_drag(&mut self, input: &WinitInputHelper, events: &mut Vec<replay::GridEvent>) {
+        for (button, frozen) in [(winit::event::MouseButton::Left, true), (winit::event::MouseButton::Right, false)] {
+            if input.mouse_pressed(button) {
+                self.freeze_drag_start = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y));
+            }
+            if input.mouse_released(button) {
+                let start = self.freeze_drag_start.take();
+                let end = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y));
+                if let (Some((x0, y0)), Some((x1, y1))) = (start, end) {
+                    events.push(replay::GridEvent::Freeze {
+                        x: x0.min(x1),
+                        y: y0.min(y1),
+                        width: x0.abs_diff(x1) + 1,
+                        height: y0.abs_diff(y1) + 1,
+                        frozen,
+                    });
+                }
+            }
+        }
     }
-}
 
+    // Adds the cells under the brush at (x, y) to the current stroke before they are painted over
+    fn record_brush(&mut self, x: usize, y: usize, shape: BrushShape) {
+        let (low, high) = shape.bounds();
+        let (x0, y0) = (x.saturating_add_signed(low), y.saturating_add_signed(low));
+        let region = self.sand_data.copy_region(x0, y0, x.saturating_add_signed(high) - x0, y.saturating_add_signed(high) - y0);
+        self.history.record(region);
+    }
 
-impl MyApp {
-    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    // A drag with the line brush draws a line of the current material from the
+    // cell it starts on to the one it ends on with the left button, and erases
+    // one with the right button
+    fn line_drag(&mut self, input: &WinitInputHelper, events: &mut Vec<replay::GridEvent>) {
+        for (button, material) in [(winit::event::MouseButton::Left, self.current_material), (winit::event::MouseButton::Right, Material::Empty)] {
+            if input.mouse_pressed(button) {
+                self.line_start = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y));
+            }
+            if input.mouse_released(button) {
+                let start = self.line_start.take();
+                let end = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y));
+                if let (Some(from), Some(to)) = (start, end) {
+                    events.push(replay::GridEvent::Line { from, to, material });
+                }
+            }
+        }
+    }
 
-    fn create_depth_texture(
-        config: &wgpu::SurfaceConfiguration,
-        device: &wgpu::Device,
-    ) -> wgpu::TextureView {
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            label: None,
-            view_formats: &[],
-        });
+    // Outlines the cells the brush would paint under the cursor, or the line a
+    // drag with the line brush would draw. Only the edge of the square and the
+    // circle is outlined so the cells under it stay visible.
+    fn update_brush_preview(&mut self, input: &WinitInputHelper) {
+        let cursor = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)).filter(|_| !self.inspecting);
+        let (width, height) = (self.sand_data.width, self.sand_data.height);
+        let mut cells = Vec::new();
+        match (cursor, self.brush.shape(), self.line_start) {
+            (None, _, _) => {}
+            (Some((x, y)), Some(shape), _) => {
+                for (dx, dy) in shape.offsets() {
+                    let edge = [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().any(|(ex, ey)| !shape.covers(dx + ex, dy + ey));
+                    if let (true, Some(x), Some(y)) = (edge, x.checked_add_signed(dx), y.checked_add_signed(dy)) {
+                        if x < width && y < height {
+                            cells.push((x, y));
+                        }
+                    }
+                }
+            }
+            (Some((x, y)), None, Some(start)) => {
+                let line = sand_grid::line_cells((start.0 as isize, start.1 as isize), (x as isize, y as isize));
+                cells.extend(line.into_iter().map(|(x, y)| (x as usize, y as usize)));
+            }
+            (Some(cell), None, None) => cells.push(cell),
+        }
+        if cells == self.brush_preview_cells {
+            return;
+        }
+        for &(x, y) in &self.brush_preview_cells {
+            self.brush_preview.set_pixel(x, y, 0, 0, 0, 0);
+        }
+        for &(x, y) in &cells {
+            self.brush_preview.set_pixel(x, y, 255, 255, 255, BRUSH_PREVIEW_ALPHA);
+        }
+        self.brush_preview_cells = cells;
+        self.brush_preview_dirty = true;
+    }
 
-        depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
+    // Clears the color target to the background or keeps what the previous
+    // frame drew there. The sand quad is redrawn whole from its texture every
+    // frame, so only partially uploading it doesn't leave stale cells behind
+    // either way, only what is drawn around it or moved away from accumulates.
+    // Surface textures start out cleared rather than holding the previous frame,
+    // the persistent MSAA target is the one that really accumulates.
+    fn color_load_op(&self) -> wgpu::LoadOp<wgpu::Color> {
+        if !self.clear_color {
+            return wgpu::LoadOp::Load;
+        }
+        wgpu::LoadOp::Clear(wgpu::Color {
+            r: self.background[0] as f64,
+            g: self.background[1] as f64,
+            b: self.background[2] as f64,
+            a: self.background[3] as f64,
+        })
     }
 
-}
+    // Uniforms of `fs_grid` and `fs_checker`: the grid size in cells, the spacing
+    // of the lines and the size of the checker squares
+    fn grid_lines_uniform(grid: &SandGrid) -> [f32; 4] {
+        [grid.width as f32, grid.height as f32, GRID_LINE_SPACING, CHECKER_SIZE]
+    }
 
+    fn load_grid(&mut self, path: &str) -> anyhow::Result<()> {
+        let mut grid = SandGrid::load(path)?;
+        if grid.width != self.sand_data.width || grid.height != self.sand_data.height {
+            anyhow::bail!(
+                "{path} holds a {}x{} grid but the current grid is {}x{}",
+                grid.width, grid.height, self.sand_data.width, self.sand_data.height);
+        }
+        grid.copy_settings_from(&self.sand_data);
+        grid.set_hdr(self.hdr);
+        self.stop_scrubbing();
+        self.sand_data = grid;
+        self.history.clear();
+        self.snapshots.clear();
+        Ok(())
+    }
 
-impl crate::wgpu_app::App for MyApp {
-    fn init(
+    // Builds the app and all of its GPU resources, around `grid` when given or
+    // a grid set up from the command line
+    fn create(
         window: Arc<Window>,
         config: &wgpu::SurfaceConfiguration,
-        _adapter: &wgpu::Adapter,
+        adapter: &wgpu::Adapter,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        command_line: CommandLine,
+        grid: Option<SandGrid>,
     ) -> Self {
 
 
-        let vertex_size = size_of::<Vertex>();
         let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -283,134 +1564,246 @@ impl crate::wgpu_app::App for MyApp {
             push_constant_ranges: &[],
         });
 
+        //the grid lines pass binds its uniforms next to the model transform
+        let grid_lines_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<Mat4>() as _),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<[f32; 4]>() as _),
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let grid_lines_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &transform_matrix_bind_group_layout, //projection
+                &transform_matrix_bind_group_layout, //view (camera)
+                &grid_lines_bind_group_layout, //model and grid lines
+                &texture_bind_group_layout
+            ],
+            push_constant_ranges: &[],
+        });
+
         // Create other resources
         let aspect_ratio = config.width as f32 / config.height as f32;
-        //let projection = utils::create_perspective_matrix(aspect_ratio);
         let projection = utils::create_iso_matrix(config.width as _, config.height as _);
         let (projection_buffer, projection_bindgroup) = utils::create_matrix_buffer_and_bind_group(device, "projection", &transform_matrix_bind_group_layout, &projection);
+        //the overlay ignores pan and zoom
+        let (screen_projection_buffer, screen_projection_bindgroup) = utils::create_matrix_buffer_and_bind_group(device, "screen projection", &transform_matrix_bind_group_layout, &projection);
 
-        let cam_pos = glam::Vec3::new(0.0, -100.0, 0.0);
-        let cam_rot = glam::Quat::from_rotation_arc(glam::Vec3::new(0.0, 1.0, 0.0), (glam::Vec3::new(0.0, 0.0, 0.0) - cam_pos).normalize());
-        let camera = utils::get_view_matrix(cam_pos, cam_rot);
+        let camera = Self::ortho_camera();
         let (camera_buffer, camera_bindgroup) = utils::create_matrix_buffer_and_bind_group(device, "camera", &transform_matrix_bind_group_layout, &camera);
-
-        let wgsl_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/shader.wgsl"));
-
-        let vertex_buffer_layout = [wgpu::VertexBufferLayout {
-            array_stride: vertex_size as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute { //pos
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: 0,
-                    shader_location: 0,
-                },
-                wgpu::VertexAttribute { //color
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: 4 * 3,
-                    shader_location: 1,
-                },
-                wgpu::VertexAttribute { //uv
-                    format: wgpu::VertexFormat::Float32x2,
-                    offset: (4 * 3) + (4 * 3),
-                    shader_location: 2,
-                },
-            ],
-        }];
+        //the overlay keeps looking straight at the screen in perspective mode
+        let (_, screen_camera_bindgroup) = utils::create_matrix_buffer_and_bind_group(device, "screen camera", &transform_matrix_bind_group_layout, &camera);
 
         let quad_width = config.width as _;
         let quad_height = config.height as _;
-        let quad_transform_matrix = glam::Mat4::from_translation(Vec3::new(0.0,0.0, 0.0));
-        let (_, quad_uniform_bind_group) = utils::create_matrix_buffer_and_bind_group(device, "quad", &transform_matrix_bind_group_layout, &quad_transform_matrix);
-        let sand_data = SandGrid::new(config.width as _, config.height as _);
-        let material = primitives::create_custom_tex_material(device, queue, &texture_bind_group_layout, &sand_data.color );
+        let (grid_width, grid_height) = match (command_line.grid_size, command_line.sim_scale) {
+            (Some(size), Some(_)) => {
+                log::warn!("Both a grid size and a simulation scale were given, using the grid size");
+                size
+            }
+            (Some(size), None) => size,
+            //nearest filtering keeps the cells crisp squares of `scale` pixels
+            (None, Some(scale)) => (config.width as usize / scale, config.height as usize / scale),
+            (None, None) => (GRID_WIDTH, GRID_HEIGHT),
+        };
+        let max_texture_size = device.limits().max_texture_dimension_2d as usize;
+        let mut sand_data = match (grid, &command_line.initial_image) {
+            (Some(grid), _) => grid,
+            (None, Some(path)) => Self::initial_grid_from_image(path, grid_width, grid_height).unwrap_or_else(|e| {
+                log::error!("Unable to load the initial grid from {path}: {e}");
+                Self::empty_grid(grid_width, grid_height, max_texture_size)
+            }),
+            (None, None) => Self::empty_grid(grid_width, grid_height, max_texture_size),
+        };
+        if let Some(build) = command_line.preset.as_deref().and_then(presets::find) {
+            build(&mut sand_data);
+        }
+        if let Some(path) = &command_line.stamp {
+            match model::Model::load_model(device, queue, path, &texture_bind_group_layout) {
+                Ok(model) => sand_data.stamp_model_outline(&model.outline, Material::Stone),
+                Err(e) => log::error!("Unable to stamp the outline of {path}: {e}"),
+            }
+        }
+        let quad_transform_matrix = Self::quad_fit_transform(glam::Vec2::new(quad_width, quad_height), glam::Vec2::new(quad_width, quad_height), &sand_data);
+        let (quad_uniform_buffer, quad_uniform_bind_group) = utils::create_matrix_buffer_and_bind_group(device, "quad", &transform_matrix_bind_group_layout, &quad_transform_matrix);
+        let sand_texture_options = Self::sand_texture_options(texture::TextureOptions::default().format);
+        let material = primitives::create_custom_tex_material(device, queue, &texture_bind_group_layout, &sand_data.color, &sand_texture_options);
         let quad_model = std::rc::Rc::new(std::cell::RefCell::new(primitives::Quad::new(device, &glam::Vec2::new(quad_width,quad_height), material)));
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &wgsl_shader,
-                entry_point: "vs_main",
-                compilation_options: Default::default(),
-                buffers: &vertex_buffer_layout,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &wgsl_shader,
-                entry_point: "fs_main",
-                compilation_options: Default::default(),
-                targets: &[Some(config.view_formats[0].into())],
-            }),
-            primitive: wgpu::PrimitiveState {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Self::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        let brush_preview = CpuTexture::new(sand_data.width, sand_data.height, cpu_texture::new_texture(sand_data.width, sand_data.height));
+        let brush_preview_options = texture::TextureOptions { filter: wgpu::FilterMode::Nearest, ..Default::default() };
+        let brush_preview_material = primitives::create_custom_tex_material(device, queue, &texture_bind_group_layout, &brush_preview, &brush_preview_options);
+        let brush_preview_model = primitives::Quad::new(device, &glam::Vec2::new(quad_width, quad_height), brush_preview_material);
+        let (brush_preview_buffer, brush_preview_bind_group) = utils::create_matrix_buffer_and_bind_group(device, "brush preview", &transform_matrix_bind_group_layout, &Self::brush_preview_transform(quad_transform_matrix));
 
-        let pipeline_wire = if device
-            .features()
-            .contains(wgpu::Features::POLYGON_MODE_LINE)
-        {
-            let pipeline_wire = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &wgsl_shader,
-                    entry_point: "vs_main",
-                    compilation_options: Default::default(),
-                    buffers: &vertex_buffer_layout,
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &wgsl_shader,
-                    entry_point: "fs_wire",
-                    compilation_options: Default::default(),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.view_formats[0],
-                        blend: Some(wgpu::BlendState {
-                            color: wgpu::BlendComponent {
-                                operation: wgpu::BlendOperation::Add,
-                                src_factor: wgpu::BlendFactor::SrcAlpha,
-                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            },
-                            alpha: wgpu::BlendComponent::REPLACE,
-                        }),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: None,
-                    polygon_mode: wgpu::PolygonMode::Line,
-                    ..Default::default()
+        //the frame shares the uniform buffer of the quad so it follows it on resize
+        let frame_material = primitives::create_color_material(device, queue, &texture_bind_group_layout, 60, 60, 60);
+        let frame_model = Rc::new(RefCell::new(primitives::Frame::new(device, &glam::Vec2::new(quad_width,quad_height), FRAME_THICKNESS, frame_material)));
+        let frame_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &transform_matrix_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: quad_uniform_buffer.as_entire_binding(),
+                }
+            ],
+            label: None,
+        });
+        let iso_transform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &transform_matrix_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: quad_uniform_buffer.as_entire_binding(),
+                }
+            ],
+            label: None,
+        });
+        let grid_lines_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("grid lines Uniform Buffer"),
+            contents: bytemuck::cast_slice(&Self::grid_lines_uniform(&sand_data)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let grid_lines_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &grid_lines_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: quad_uniform_buffer.as_entire_binding(),
                 },
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: Self::DEPTH_FORMAT,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-                cache: None,
-            });
-            Some(pipeline_wire)
-        } else {
-            None
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: grid_lines_buffer.as_entire_binding(),
+                }
+            ],
+            label: None,
+        });
+        let drawables = vec![
+            (quad_model.clone(), quad_uniform_bind_group),
+            (frame_model, frame_uniform_bind_group),
+        ];
+
+        //the overlay sits in the top left corner, slightly in front of the sand quad
+        let overlay = overlay::TextOverlay::new(OVERLAY_WIDTH, OVERLAY_HEIGHT);
+        let overlay_transform_matrix = glam::Mat4::from_translation(Vec3::new(8.0, -1.0, 8.0));
+        let (_, overlay_uniform_bind_group) = utils::create_matrix_buffer_and_bind_group(device, "overlay", &transform_matrix_bind_group_layout, &overlay_transform_matrix);
+        let overlay_material = primitives::create_custom_tex_material(device, queue, &texture_bind_group_layout, overlay.texture(), &Default::default());
+        let overlay_size = glam::Vec2::new(OVERLAY_WIDTH as f32 * OVERLAY_SCALE, OVERLAY_HEIGHT as f32 * OVERLAY_SCALE);
+        let overlay_model = primitives::Quad::new(device, &overlay_size, overlay_material);
+
+        //multisampled copies of the pipelines, when the surface format supports it
+        let msaa_supported = adapter
+            .get_texture_format_features(config.view_formats[0])
+            .flags
+            .sample_count_supported(MSAA_SAMPLE_COUNT);
+        let msaa_targets = msaa_supported.then(|| Self::create_msaa_targets(config, device));
+        let shader_file = command_line.shader.as_deref().and_then(|path| {
+            match Self::compile_shader_file(device, path, &pipeline_layout, &grid_lines_pipeline_layout, config.view_formats[0], msaa_supported) {
+                Ok(pipelines) => {
+                    log::info!("Loaded the shader from {path}");
+                    Some(pipelines)
+                }
+                Err(e) => {
+                    log::error!("Unable to load the shader from {path}, using the embedded one: {e:#}");
+                    None
+                }
+            }
+        });
+        let (pipelines, msaa_pipelines, hdr_pipelines) = shader_file.unwrap_or_else(|| {
+            let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/shader.wgsl"));
+            let pipeline_inputs = PipelineInputs {
+                shader: &shader,
+                layout: &pipeline_layout,
+                grid_lines_layout: &grid_lines_pipeline_layout,
+                vertex_buffers: &[Self::VERTEX_BUFFER_LAYOUT],
+                instanced_buffers: &[Self::VERTEX_BUFFER_LAYOUT, Self::INSTANCE_BUFFER_LAYOUT],
+            };
+            Self::create_scene_pipelines(device, &pipeline_inputs, config.view_formats[0], msaa_supported)
+        });
+        let blit_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/blit.wgsl"));
+        let blit_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let copy_pipeline = Self::create_fullscreen_pipeline(device, &blit_layout, &blit_shader, "fs_copy", config.view_formats[0].into());
+        let tonemap_pipeline = Self::create_fullscreen_pipeline(device, &blit_layout, &blit_shader, "fs_tonemap", config.view_formats[0].into());
+
+        let bloom_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<[f32; 4]>() as _),
+                    },
+                    count: None,
+                }
+            ],
+        });
+        let bloom_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("bloom Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[LDR_BLOOM_THRESHOLD, BLOOM_INTENSITY, 0.0, 0.0]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bloom_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bloom_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: bloom_buffer.as_entire_binding(),
+                }
+            ],
+            label: None,
+        });
+        let bloom_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/bloom.wgsl"));
+        let bloom_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &texture_bind_group_layout, //source
+                &bloom_bind_group_layout, //threshold and intensity
+            ],
+            push_constant_ranges: &[],
+        });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let bloom_pipelines = BloomPipelines {
+            extract: Self::create_fullscreen_pipeline(device, &bloom_layout, &bloom_shader, "fs_extract", Self::HDR_FORMAT.into()),
+            blur_horizontal: Self::create_fullscreen_pipeline(device, &bloom_layout, &bloom_shader, "fs_blur_horizontal", Self::HDR_FORMAT.into()),
+            blur_vertical: Self::create_fullscreen_pipeline(device, &bloom_layout, &bloom_shader, "fs_blur_vertical", Self::HDR_FORMAT.into()),
+            composite: Self::create_fullscreen_pipeline(device, &bloom_layout, &bloom_shader, "fs_composite", wgpu::ColorTargetState {
+                format: Self::HDR_FORMAT,
+                blend: Some(wgpu::BlendState { color: additive, alpha: additive }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
         };
 
-        let forward_depth = Self::create_depth_texture(config, device);
+        let forward_depth = Self::create_depth_texture(config, device, 1);
 
         let frame_timer = utils::FrameTime::new();
 
@@ -419,23 +1812,203 @@ impl crate::wgpu_app::App for MyApp {
         MyApp {
             window,
             forward_depth,
-            pipeline,
-            pipeline_wire,
+            pipelines,
+            msaa_pipelines,
+            msaa_targets,
+            msaa: false,
+            hdr_pipelines,
+            copy_pipeline,
+            tonemap_pipeline,
+            bloom_pipelines,
+            bloom_buffer,
+            bloom_bind_group,
+            hdr: false,
+            bloom: false,
+            offscreen: None,
+            ldr_sand_material: None,
+            texture_bind_group_layout,
+            pipeline_layout,
+            grid_lines_pipeline_layout,
+            surface_format: config.view_formats[0],
+            shader_watch: command_line.shader.clone().map(|path| ShaderWatch {
+                modified: std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok(),
+                checked: std::time::Instant::now(),
+                path,
+            }),
+            shader_reload_requested: false,
+            clear_color: true,
+            grid_lines_buffer,
+            grid_lines_bind_group,
+            iso_columns: iso_columns::IsoColumns::new(device),
+            iso_transform_bind_group,
+            show_iso_columns: false,
+            show_grid_lines: false,
+            show_checker: false,
+            gpu_screenshot_requested: false,
             projection_buffer,
             projection_bindgroup,
+            screen_projection_buffer,
+            screen_projection_bindgroup,
+            viewport_size: glam::Vec2::new(config.width as _, config.height as _),
+            pan: glam::Vec2::ZERO,
+            middle_press_position: None,
+            freeze_drag_start: None,
+            brush: Brush::DEFAULT,
+            line_start: None,
+            brush_preview,
+            brush_preview_cells: Vec::new(),
+            brush_preview_dirty: false,
+            brush_preview_model,
+            brush_preview_buffer,
+            brush_preview_bind_group,
+            margin_click: None,
+            inspecting: false,
+            probe: None,
+            zoom: 1.0,
+            projection_dirty: false,
+            projection,
+            camera,
+            perspective: false,
+            orbit_yaw: 0.0,
+            orbit_tilt: PERSPECTIVE_TILT,
+            background: command_line.background.unwrap_or(DEFAULT_BACKGROUND),
+            quad_size: glam::Vec2::new(quad_width, quad_height),
             camera_buffer,
             camera_bindgroup,
+            screen_camera_bindgroup,
             sand_data,
-            quad_uniform_bind_group,
+            quad_uniform_buffer,
+            quad_transform: quad_transform_matrix,
             quad_model,
+            drawables,
+            overlay,
+            overlay_model,
+            overlay_uniform_bind_group,
+            show_overlay: false,
+            overlay_dirty: false,
+            overlay_frame_count: 0,
+            overlay_elapsed: std::time::Duration::new(0, 0),
             aspect_ratio,
             frame_timer,
             show_wire: false,
+            current_material: Material::Sand,
+            parallel_simulation: false,
+            paused: false,
+            step_count: 0,
+            tick_accumulator: 0.0,
+            time_scale: 1.0,
+            pending_events: Vec::new(),
+            max_steps: command_line.max_steps,
             simulate_time: std::time::Duration::new(0, 0),
             texture_upload_time: std::time::Duration::new(0, 0),
+            history: history::History::new(HISTORY_CAPACITY),
+            snapshots: snapshots::Snapshots::new(
+                command_line.snapshots.unwrap_or(DEFAULT_SNAPSHOT_COUNT),
+                command_line.snapshot_interval.unwrap_or(DEFAULT_SNAPSHOT_INTERVAL)),
+            scrub: None,
+            recorder: command_line.record.as_deref().and_then(|path| match replay::Recorder::create(path) {
+                Ok(recorder) => {
+                    log::info!("Recording input to {path}");
+                    Some(recorder)
+                }
+                Err(e) => {
+                    log::error!("Unable to record input to {path}: {e}");
+                    None
+                }
+            }),
+            replay: command_line.replay.as_deref().and_then(|path| match replay::Replay::load(path) {
+                Ok(replay) => {
+                    log::info!("Replaying input from {path}");
+                    Some(replay)
+                }
+                Err(e) => {
+                    log::error!("Unable to replay input from {path}: {e:#}");
+                    None
+                }
+            }),
         }
     }
 
+}
+
+
+impl crate::wgpu_app::App for MyApp {
+    fn init(
+        window: Arc<Window>,
+        config: &wgpu::SurfaceConfiguration,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        Self::create(window, config, adapter, device, queue, CommandLine::current().clone(), None)
+    }
+
+    // Only the GPU resources are created again, moved over from a new app built
+    // around the current grid so its texture starts from the current colors
+    fn device_recreated(
+        &mut self,
+        window: Arc<Window>,
+        config: &wgpu::SurfaceConfiguration,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        //recording and replaying carry on with the files already open, the
+        //preset and the stamped outline are already on the kept grid
+        let command_line = CommandLine { record: None, replay: None, preset: None, stamp: None, ..CommandLine::current().clone() };
+        let grid = std::mem::replace(&mut self.sand_data, SandGrid::new(1, 1).expect("a 1x1 grid is valid"));
+        let fresh = Self::create(window, config, adapter, device, queue, command_line, Some(grid));
+        self.sand_data = fresh.sand_data;
+        self.window = fresh.window;
+        self.forward_depth = fresh.forward_depth;
+        self.pipelines = fresh.pipelines;
+        self.msaa_pipelines = fresh.msaa_pipelines;
+        self.msaa_targets = fresh.msaa_targets;
+        self.hdr_pipelines = fresh.hdr_pipelines;
+        self.copy_pipeline = fresh.copy_pipeline;
+        self.tonemap_pipeline = fresh.tonemap_pipeline;
+        self.bloom_pipelines = fresh.bloom_pipelines;
+        self.bloom_buffer = fresh.bloom_buffer;
+        self.bloom_bind_group = fresh.bloom_bind_group;
+        self.texture_bind_group_layout = fresh.texture_bind_group_layout;
+        self.pipeline_layout = fresh.pipeline_layout;
+        self.grid_lines_pipeline_layout = fresh.grid_lines_pipeline_layout;
+        self.surface_format = fresh.surface_format;
+        //made again by the next `render` if HDR or bloom is on, the new quad starts with the sRGB colors
+        self.offscreen = None;
+        self.ldr_sand_material = None;
+        self.grid_lines_buffer = fresh.grid_lines_buffer;
+        self.grid_lines_bind_group = fresh.grid_lines_bind_group;
+        self.iso_columns = fresh.iso_columns;
+        self.iso_transform_bind_group = fresh.iso_transform_bind_group;
+        self.projection_buffer = fresh.projection_buffer;
+        self.projection_bindgroup = fresh.projection_bindgroup;
+        self.screen_projection_buffer = fresh.screen_projection_buffer;
+        self.screen_projection_bindgroup = fresh.screen_projection_bindgroup;
+        self.camera_buffer = fresh.camera_buffer;
+        self.camera_bindgroup = fresh.camera_bindgroup;
+        self.screen_camera_bindgroup = fresh.screen_camera_bindgroup;
+        self.quad_size = fresh.quad_size;
+        self.quad_uniform_buffer = fresh.quad_uniform_buffer;
+        self.quad_model = fresh.quad_model;
+        self.drawables = fresh.drawables;
+        self.overlay_model = fresh.overlay_model;
+        self.overlay_uniform_bind_group = fresh.overlay_uniform_bind_group;
+        //the new texture starts out transparent, the preview is drawn again on the next update
+        self.brush_preview = fresh.brush_preview;
+        self.brush_preview_cells.clear();
+        self.brush_preview_model = fresh.brush_preview_model;
+        self.brush_preview_buffer = fresh.brush_preview_buffer;
+        self.brush_preview_bind_group = fresh.brush_preview_bind_group;
+        //the new buffers hold the initial view, write the current one
+        self.resize(config, device, queue);
+        self.overlay_dirty = true;
+    }
+
+    // The grid keeps its cells across resizes, only the quad it is drawn on is
+    // fitted to the new window. Cursor positions go
+    // through `screen_to_grid`, so they always land inside the grid whatever the
+    // window size.
     fn resize(
         &mut self,
         config: &wgpu::SurfaceConfiguration,
@@ -443,11 +2016,26 @@ impl crate::wgpu_app::App for MyApp {
         queue: &wgpu::Queue,
     ) {
         self.aspect_ratio = config.width as f32 / config.height as f32;
-        //let new_proj_mat = utils::create_perspective_matrix(self.aspect_ratio);
         let new_proj_mat = utils::create_iso_matrix(config.width as _, config.height as _);
         let mx_ref: &[f32; 16] = new_proj_mat.as_ref();
-        queue.write_buffer(&self.projection_buffer, 0, bytemuck::cast_slice(mx_ref));
-        self.forward_depth = Self::create_depth_texture(config, device);
+        queue.write_buffer(&self.screen_projection_buffer, 0, bytemuck::cast_slice(mx_ref));
+        self.viewport_size = glam::Vec2::new(config.width as _, config.height as _);
+        self.write_projection(queue);
+
+        //keep the quad fitted to the window, the grid itself is left untouched
+        self.quad_transform = Self::quad_fit_transform(self.viewport_size, self.quad_size, &self.sand_data);
+        let mx_ref: &[f32; 16] = self.quad_transform.as_ref();
+        queue.write_buffer(&self.quad_uniform_buffer, 0, bytemuck::cast_slice(mx_ref));
+        let preview_transform = Self::brush_preview_transform(self.quad_transform);
+        let mx_ref: &[f32; 16] = preview_transform.as_ref();
+        queue.write_buffer(&self.brush_preview_buffer, 0, bytemuck::cast_slice(mx_ref));
+        self.forward_depth = Self::create_depth_texture(config, device, 1);
+        if self.msaa_targets.is_some() {
+            self.msaa_targets = Some(Self::create_msaa_targets(config, device));
+        }
+        if self.offscreen.is_some() {
+            self.offscreen = Some(Self::create_offscreen_targets(config.width, config.height, device, &self.texture_bind_group_layout));
+        }
     }
 
     fn process_event(&mut self, _event: &winit::event::Event<()>) {
@@ -460,46 +2048,426 @@ impl crate::wgpu_app::App for MyApp {
 
         const ZOOM_SPEED:f32 = 5.0;
 
-        if input.mouse_pressed(winit::event::MouseButton::Left) || input.mouse_held(winit::event::MouseButton::Left) {
-            if let Some((x,y)) = input.cursor() {
-                if x >= 0.0 && y >= 0.0 && x < self.sand_data.width as _ && y < self.sand_data.height as _ {
-                    self.sand_data.spawn_sand_at(x as _, y as _)    
+        self.update_camera(input, dt_as_sec);
+
+        let mut events = Vec::new();
+        if self.inspecting {
+            if input.mouse_pressed(winit::event::MouseButton::Left) {
+                if let Some((x, y)) = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)) {
+                    let passes = self.sand_data.pass_count(x, y).unwrap_or(0);
+                    self.probe = Some(Probe { x, y, passes, flow: 0.0 });
+                    log::info!("Probing {}", self.probe_lines().join(", "));
+                }
+            }
+        } else if input.held_alt() {
+            self.freeze_drag(input, &mut events);
+        } else if input.held_shift() {
+            //shift click fills the region under the cursor instead of painting
+            if input.mouse_pressed(winit::event::MouseButton::Left) {
+                if let Some((x,y)) = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)) {
+                    events.push(replay::GridEvent::Fill { x, y, material: self.current_material });
+                }
+            }
+        } else if let Some(shape) = self.brush.shape() {
+            if input.mouse_pressed(winit::event::MouseButton::Left) || input.mouse_held(winit::event::MouseButton::Left) {
+                if let Some((x,y)) = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)) {
+                    events.push(replay::GridEvent::Spawn { x, y, material: self.current_material, shape });
                 }
             }
+        } else {
+            self.line_drag(input, &mut events);
         }
 
-        let timer = std::time::Instant::now();
-        self.sand_data.simulate(dt_as_sec);
-        self.simulate_time = timer.elapsed();
-        log::info!("Simulate time: {}ms", self.simulate_time.as_millis());
+        //painting never reaches outside of the grid, a double click there clears it instead
+        if !self.inspecting
+            && input.mouse_pressed(winit::event::MouseButton::Left)
+            && input.cursor().is_some_and(|(x, y)| self.screen_to_grid(x, y).is_none())
+        {
+            let now = std::time::Instant::now();
+            if self.margin_click.is_some_and(|clicked| now - clicked < DOUBLE_CLICK_INTERVAL) {
+                events.push(replay::GridEvent::Clear);
+                self.margin_click = None;
+            } else {
+                self.margin_click = Some(now);
+            }
+        }
+
+        //the line brush erases on release, see `line_drag`, and inspecting never changes the grid
+        let erase_shape = self.brush.shape().filter(|_| !input.held_alt() && !self.inspecting);
+        if let Some(shape) = erase_shape.filter(|_| input.mouse_pressed(winit::event::MouseButton::Right) || input.mouse_held(winit::event::MouseButton::Right)) {
+            if let Some((x,y)) = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)) {
+                events.push(replay::GridEvent::Erase { x, y, shape });
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::Tab) {
+            self.brush = self.brush.next();
+            self.line_start = None;
+            log::info!("Brush: {:?}", self.brush);
+        }
+        for (key, grow) in [(winit::keyboard::KeyCode::Minus, false), (winit::keyboard::KeyCode::Equal, true)] {
+            if input.key_pressed(key) {
+                self.brush = self.brush.resized(grow);
+                log::info!("Brush: {:?}", self.brush);
+            }
+        }
+        self.update_brush_preview(input);
+
+        if input.held_control() && input.key_pressed(winit::keyboard::KeyCode::KeyZ) {
+            events.push(replay::GridEvent::Undo);
+        }
+        if input.held_control() && input.key_pressed(winit::keyboard::KeyCode::KeyY) {
+            events.push(replay::GridEvent::Redo);
+        }
+
+        //A places an attractor under the cursor, shift A removes them all
+        if input.key_pressed(winit::keyboard::KeyCode::KeyA) {
+            if input.held_shift() {
+                events.push(replay::GridEvent::ClearAttractors);
+            } else if let Some((x,y)) = input.cursor().and_then(|(x, y)| self.screen_to_grid(x, y)) {
+                events.push(replay::GridEvent::Attractor { x, y });
+            }
+        }
+
+        //middle drag pans, a middle click places an emitter of the current material
+        if input.mouse_pressed(winit::event::MouseButton::Middle) {
+            self.middle_press_position = input.cursor().map(|(x, y)| glam::Vec2::new(x, y));
+        }
+        if input.mouse_released(winit::event::MouseButton::Middle) {
+            let pressed_at = self.middle_press_position.take();
+            let released_at = input.cursor().map(|(x, y)| glam::Vec2::new(x, y));
+            if let (Some(pressed_at), Some(released_at)) = (pressed_at, released_at) {
+                if pressed_at.distance(released_at) < 3.0 {
+                    if let Some((x, y)) = self.screen_to_grid(released_at.x, released_at.y) {
+                        events.push(replay::GridEvent::Emitter { x, y, material: self.current_material });
+                    }
+                }
+            }
+        }
+
+        //1 to 9 then 0 pick the paintable materials in order
+        const MATERIAL_KEYS: [winit::keyboard::KeyCode; 10] = [
+            winit::keyboard::KeyCode::Digit1,
+            winit::keyboard::KeyCode::Digit2,
+            winit::keyboard::KeyCode::Digit3,
+            winit::keyboard::KeyCode::Digit4,
+            winit::keyboard::KeyCode::Digit5,
+            winit::keyboard::KeyCode::Digit6,
+            winit::keyboard::KeyCode::Digit7,
+            winit::keyboard::KeyCode::Digit8,
+            winit::keyboard::KeyCode::Digit9,
+            winit::keyboard::KeyCode::Digit0,
+        ];
+        for (key, material) in MATERIAL_KEYS.iter().zip(Material::PAINTABLE) {
+            if input.key_pressed(*key) {
+                self.current_material = material;
+                log::info!("Painting with {}", material.name());
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyM) {
+            let current = Material::PAINTABLE.iter().position(|m| *m == self.current_material).unwrap_or(0);
+            self.current_material = Material::PAINTABLE[(current + 1) % Material::PAINTABLE.len()];
+            log::info!("Painting with {}", self.current_material.name());
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyP) {
+            self.parallel_simulation = !self.parallel_simulation;
+            log::info!("Parallel simulation: {}", self.parallel_simulation);
+        }
+
+        //the arrow keys orbit the camera in perspective instead
+        if !self.perspective && input.key_pressed(winit::keyboard::KeyCode::ArrowLeft) {
+            self.sand_data.gravity = self.sand_data.gravity.perp();
+            log::info!("Gravity: {}", self.sand_data.gravity);
+        }
+
+        if !self.perspective && input.key_pressed(winit::keyboard::KeyCode::ArrowRight) {
+            self.sand_data.gravity = -self.sand_data.gravity.perp();
+            log::info!("Gravity: {}", self.sand_data.gravity);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::BracketLeft) {
+            self.sand_data.wind = (self.sand_data.wind - WIND_STEP).max(-MAX_WIND);
+            log::info!("Wind: {}", self.sand_data.wind);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::BracketRight) {
+            self.sand_data.wind = (self.sand_data.wind + WIND_STEP).min(MAX_WIND);
+            log::info!("Wind: {}", self.sand_data.wind);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyT) {
+            let current = REPOSE_THRESHOLDS.iter().position(|t| *t == self.sand_data.repose_threshold).unwrap_or(0);
+            self.sand_data.repose_threshold = REPOSE_THRESHOLDS[(current + 1) % REPOSE_THRESHOLDS.len()];
+            log::info!("Repose threshold: {}", self.sand_data.repose_threshold);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyV) {
+            let color_mode = match self.sand_data.color_mode() {
+                ColorMode::Material => ColorMode::Velocity,
+                ColorMode::Velocity => ColorMode::Temperature,
+                ColorMode::Temperature => ColorMode::Wetness,
+                ColorMode::Wetness => ColorMode::Density,
+                ColorMode::Density => ColorMode::Material,
+            };
+            self.sand_data.set_color_mode(color_mode);
+            log::info!("Color mode: {color_mode:?}");
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyW) {
+            if self.pipelines.wire.is_some() {
+                self.show_wire = !self.show_wire;
+                log::info!("Wireframe: {}", self.show_wire);
+            } else {
+                log::warn!("Wireframe unavailable, the device doesn't support POLYGON_MODE_LINE");
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyB) {
+            //solid, wrapping, open at the bottom only, open all around
+            let presets = [BoundaryMode::SOLID, BoundaryMode::WRAP, BoundaryMode::FUNNEL, BoundaryMode::uniform(EdgeMode::Open)];
+            let current = presets.iter().position(|preset| *preset == self.sand_data.boundary);
+            self.sand_data.boundary = presets[current.map_or(0, |i| (i + 1) % presets.len())];
+            log::info!("Boundary: {:?}", self.sand_data.boundary);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyR) {
+            self.sand_data.trails_enabled = !self.sand_data.trails_enabled;
+            log::info!("Trails: {}", self.sand_data.trails_enabled);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyC) {
+            self.show_checker = !self.show_checker;
+            log::info!("Checker background: {}", self.show_checker);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyG) {
+            self.show_grid_lines = !self.show_grid_lines;
+            log::info!("Grid lines: {}", self.show_grid_lines);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyN) {
+            if self.msaa_pipelines.is_some() {
+                self.msaa = !self.msaa;
+                log::info!("MSAA {MSAA_SAMPLE_COUNT}x: {}", self.msaa);
+            } else {
+                log::warn!("MSAA unavailable, the surface format doesn't support {MSAA_SAMPLE_COUNT} samples");
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyX) {
+            self.inspecting = !self.inspecting;
+            if !self.inspecting {
+                self.probe = None;
+            }
+            log::info!("Inspecting: {}", self.inspecting);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyE) {
+            self.hdr = !self.hdr;
+            log::info!("HDR: {}", self.hdr);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyF) {
+            self.bloom = !self.bloom;
+            log::info!("Bloom: {}", self.bloom);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyI) {
+            self.show_iso_columns = !self.show_iso_columns;
+            log::info!("Iso columns: {}", self.show_iso_columns);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyK) {
+            self.clear_color = !self.clear_color;
+            log::info!("Clear color target: {}", self.clear_color);
+            if !self.clear_color && !self.msaa {
+                log::warn!("Frames only accumulate with MSAA on, the surface doesn't keep its content between frames");
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyO) {
+            self.perspective = !self.perspective;
+            self.projection_dirty = true;
+            log::info!("Perspective projection: {}", self.perspective);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyU) {
+            let aging = !self.sand_data.aging();
+            self.sand_data.set_aging(aging);
+            log::info!("Aging colors: {aging}");
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyH) {
+            self.sand_data.heat_enabled = !self.sand_data.heat_enabled;
+            log::info!("Heat simulation: {}", self.sand_data.heat_enabled);
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyS) {
+            match self.sand_data.save(SAVE_FILE_PATH) {
+                Ok(()) => log::info!("Saved grid to {SAVE_FILE_PATH}"),
+                Err(e) => log::error!("Unable to save grid to {SAVE_FILE_PATH}: {e}"),
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::KeyL) {
+            match self.load_grid(SAVE_FILE_PATH) {
+                Ok(()) => log::info!("Loaded grid from {SAVE_FILE_PATH}"),
+                Err(e) => log::error!("Unable to load grid from {SAVE_FILE_PATH}: {e}"),
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::F12) && input.held_shift() {
+            self.gpu_screenshot_requested = true;
+        } else if input.key_pressed(winit::keyboard::KeyCode::F12) {
+            match self.save_screenshot() {
+                Ok(path) => log::info!("Saved screenshot to {}", path.display()),
+                Err(e) => log::error!("Unable to save screenshot: {e}"),
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::Space) {
+            self.paused = !self.paused;
+            log::info!("Paused: {}", self.paused);
+        }
+
+        for (key, time_scale) in TIME_SCALES {
+            if input.key_pressed(key) {
+                self.time_scale = time_scale;
+                log::info!("Time scale: {time_scale}x");
+            }
+        }
+
+        //the simulation advances in fixed ticks whatever the frame rate, input
+        //waits for the next tick when a frame is shorter than one
+        if self.paused {
+            //comma and period step through the recent states of the grid
+            if input.key_pressed(winit::keyboard::KeyCode::Comma) {
+                self.scrub(true);
+            }
+            if input.key_pressed(winit::keyboard::KeyCode::Period) {
+                self.scrub(false);
+            }
+            //edits go to the grid, not to the snapshot shown
+            if !events.is_empty() {
+                self.stop_scrubbing();
+            }
+            self.apply_events(&events);
+            self.tick_accumulator = 0.0;
+        } else {
+            self.stop_scrubbing();
+            self.pending_events.extend(events);
+            self.tick_accumulator += dt_as_sec;
+            let mut ticks = 0;
+            while self.tick_accumulator >= FIXED_DT && !self.paused {
+                if ticks == MAX_TICKS_PER_FRAME {
+                    //too slow to keep up, drop the backlog rather than fall further behind
+                    self.tick_accumulator = 0.0;
+                    break;
+                }
+                self.tick_accumulator -= FIXED_DT;
+                let events = std::mem::take(&mut self.pending_events);
+                //ticks keep their real time rate, only the time they simulate is scaled
+                self.tick(FIXED_DT * self.time_scale, events);
+                ticks += 1;
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::F9) {
+            if self.shader_watch.is_some() {
+                self.shader_reload_requested = true;
+            } else {
+                log::info!("The shader is embedded, start with --shader <file> to reload it");
+            }
+        }
+
+        if input.key_pressed(winit::keyboard::KeyCode::F1) {
+            self.show_overlay = !self.show_overlay;
+        }
+        self.update_overlay(dt);
     }
 
     fn render(&mut self, view: &wgpu::TextureView, device: &wgpu::Device, queue: &wgpu::Queue) {
+        profiling::span!("render");
+
+        self.sync_post_processing(device, queue);
+        self.poll_shader(device);
 
         let timer = std::time::Instant::now();
-        self.quad_model.borrow_mut().get_material(0).diffuse_texture.set_pixels(queue,  &self.sand_data.color.get_pixels()).expect("Unable to update the texture");
+        if let Some(rect) = self.sand_data.take_dirty() {
+            profiling::span!("texture_upload");
+            let mut quad_model = self.quad_model.borrow_mut();
+            let texture = &quad_model.get_material(0).diffuse_texture;
+            //the quad texture is the HDR one while the grid keeps HDR colors
+            let pixels = match self.sand_data.hdr_color() {
+                Some(hdr) => hdr.as_bytes(),
+                None => self.sand_data.color.get_pixels().as_slice(),
+            };
+            texture.set_sub_region(queue, rect.x, rect.y, rect.width, rect.height, pixels).expect("Unable to update the texture");
+            texture.regenerate_mips(device, queue);
+        }
         self.texture_upload_time = timer.elapsed();
 
+        if std::mem::take(&mut self.gpu_screenshot_requested) {
+            match self.save_gpu_screenshot(device, queue) {
+                Ok(path) => log::info!("Saved GPU screenshot to {}", path.display()),
+                Err(e) => log::error!("Unable to save GPU screenshot: {e}"),
+            }
+        }
+
+        if self.projection_dirty {
+            self.write_projection(queue);
+        }
+
+        if self.show_grid_lines || self.show_checker {
+            queue.write_buffer(&self.grid_lines_buffer, 0, bytemuck::cast_slice(&Self::grid_lines_uniform(&self.sand_data)));
+        }
+
+        if self.show_iso_columns {
+            //cubes as deep as they are wide on screen
+            let cell_width = self.quad_transform.x_axis.x * self.quad_size.x / self.sand_data.width as f32;
+            let tile_depth = iso_columns::TILE_SIZE as f32 * cell_width;
+            self.iso_columns.update(device, queue, &self.sand_data, self.quad_size, tile_depth);
+        }
+
+        if self.brush_preview_dirty {
+            self.brush_preview_model.get_material(0).diffuse_texture.set_pixels(queue, self.brush_preview.get_pixels()).expect("Unable to update the brush preview texture");
+            self.brush_preview_dirty = false;
+        }
+
+        if self.overlay_dirty {
+            self.overlay_model.get_material(0).diffuse_texture.set_pixels(queue, self.overlay.texture().get_pixels()).expect("Unable to update the overlay texture");
+            self.overlay_dirty = false;
+        }
+
+        if self.bloom {
+            queue.write_buffer(&self.bloom_buffer, 0, bytemuck::cast_slice(&self.bloom_uniform()));
+        }
+
+        //with MSAA the scene is drawn into the multisampled targets and resolved to `view`,
+        //with post processing into the offscreen targets and copied to `view` afterwards
+        let (pipelines, color_view, resolve_target, depth_view) = match (&self.offscreen, &self.msaa_pipelines, &self.msaa_targets) {
+            (Some(offscreen), _, _) => (&self.hdr_pipelines, &offscreen.color, None, &offscreen.depth),
+            (None, Some(msaa_pipelines), Some(msaa_targets)) if self.msaa => (msaa_pipelines, &msaa_targets.color, Some(view), &msaa_targets.depth),
+            _ => (&self.pipelines, view, None, &self.forward_depth),
+        };
+
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: self.color_load_op(),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment:  Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.forward_depth,
+                    view: depth_view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Discard,
@@ -509,16 +2477,80 @@ impl crate::wgpu_app::App for MyApp {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
+            if self.show_checker {
+                rpass.set_pipeline(&pipelines.checker);
+                rpass.draw_model(&self.projection_bindgroup, &self.camera_bindgroup, &self.quad_model.borrow(), &self.grid_lines_bind_group);
+            }
+
             rpass.push_debug_group("Prepare data for draw.");
-            rpass.set_pipeline(&self.pipeline);
+            //the wireframe shows back faces too
+            let (pipeline, pipeline_culled) = match &pipelines.wire {
+                Some(pipeline_wire) if self.show_wire => (pipeline_wire, pipeline_wire),
+                _ => (&pipelines.main, &pipelines.main_culled),
+            };
             rpass.pop_debug_group();
             rpass.insert_debug_marker("Draw!");
-            rpass.draw_model(&self.projection_bindgroup, &self.camera_bindgroup, &self.quad_model.borrow(), &self.quad_uniform_bind_group);
+            for (model, transform) in &self.drawables {
+                if self.show_iso_columns && Rc::ptr_eq(model, &self.quad_model) {
+                    continue;
+                }
+                let model = model.borrow();
+                rpass.set_pipeline(if model.cull_back_faces { pipeline_culled } else { pipeline });
+                rpass.draw_model(&self.projection_bindgroup, &self.camera_bindgroup, &model, transform);
+            }
+
+            if self.show_iso_columns {
+                rpass.set_pipeline(&pipelines.iso);
+                rpass.set_bind_group(0, &self.projection_bindgroup, &[]);
+                rpass.set_bind_group(1, &self.camera_bindgroup, &[]);
+                rpass.set_bind_group(2, &self.iso_transform_bind_group, &[]);
+                //unused by `fs_color` but part of the pipeline layout
+                rpass.set_bind_group(3, &self.quad_model.borrow().materials[0].bind_group, &[]);
+                self.iso_columns.draw(&mut rpass);
+            }
+
+            if self.show_grid_lines {
+                rpass.set_pipeline(&pipelines.grid_lines);
+                rpass.draw_model(&self.projection_bindgroup, &self.camera_bindgroup, &self.quad_model.borrow(), &self.grid_lines_bind_group);
+            }
+
+            //after the grid lines, it writes its depth all over the quad
+            if !self.brush_preview_cells.is_empty() {
+                rpass.set_pipeline(&pipelines.overlay);
+                rpass.draw_model(&self.projection_bindgroup, &self.camera_bindgroup, &self.brush_preview_model, &self.brush_preview_bind_group);
+            }
 
+            if self.show_overlay {
+                rpass.set_pipeline(&pipelines.overlay);
+                rpass.draw_model(&self.screen_projection_bindgroup, &self.screen_camera_bindgroup, &self.overlay_model, &self.overlay_uniform_bind_group);
+            }
+
+        }
+
+        if let Some(offscreen) = &self.offscreen {
+            if self.bloom {
+                self.draw_bloom(&mut encoder, offscreen);
+            }
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Processing"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(if self.hdr { &self.tonemap_pipeline } else { &self.copy_pipeline });
+            rpass.set_bind_group(0, &offscreen.color_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
         }
 
         queue.submit(Some(encoder.finish()));
-        log::info!("Texture upload time: {}ms", self.texture_upload_time.as_millis());
     }
 }
 
@@ -526,5 +2558,8 @@ impl crate::wgpu_app::App for MyApp {
 
 fn main() {
     println!("Hello, world!");
-    crate::wgpu_app::run::<MyApp>("My App");
+    let _profiler = profiling::init();
+    crate::wgpu_app::init_logger();
+    let command_line = CommandLine::current();
+    crate::wgpu_app::run::<MyApp>(command_line.title.as_deref().unwrap_or(CommandLine::TITLE), command_line.window_size());
 }
@@ -0,0 +1,235 @@
+// What the two cells of a reaction turn into, see `SandGrid::add_reaction`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReactionResult {
+    pub a: Material,
+    pub b: Material,
+}
+
+// Materials a grid cell can hold, stored in `SandGrid::meta` by id
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Material {
+    Empty = 0,
+    Sand = 1,
+    Fire = 2,
+    Stone = 3,
+    Water = 4,
+    Smoke = 5,
+    Wood = 6,
+    Steam = 7,
+    Lava = 8,
+    Obsidian = 9,
+    Oil = 10,
+    Acid = 11,
+    Glass = 12,
+    Snow = 13,
+}
+
+impl Material {
+    // Materials the user can paint, in the order they are cycled through and
+    // bound to the number keys
+    pub const PAINTABLE: [Material; 11] = [Material::Sand, Material::Water, Material::Stone, Material::Lava, Material::Wood, Material::Fire, Material::Smoke, Material::Oil, Material::Acid, Material::Glass, Material::Snow];
+
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            1 => Material::Sand,
+            2 => Material::Fire,
+            3 => Material::Stone,
+            4 => Material::Water,
+            5 => Material::Smoke,
+            6 => Material::Wood,
+            7 => Material::Steam,
+            8 => Material::Lava,
+            9 => Material::Obsidian,
+            10 => Material::Oil,
+            11 => Material::Acid,
+            12 => Material::Glass,
+            13 => Material::Snow,
+            _ => Material::Empty,
+        }
+    }
+
+    // Empty or the paintable material whose color is nearest to `color`, mostly
+    // transparent colors are empty. Lets scenes be drawn with the material colors.
+    pub fn closest_to_color(color: (u8, u8, u8, u8)) -> Material {
+        let (r, g, b, a) = color;
+        if a < 128 {
+            return Material::Empty;
+        }
+        let distance = |material: Material| {
+            let (mr, mg, mb, _) = material.color();
+            [(r, mr), (g, mg), (b, mb)]
+                .into_iter()
+                .map(|(c, mc)| (c as i32 - mc as i32).pow(2))
+                .sum::<i32>()
+        };
+        std::iter::once(Material::Empty)
+            .chain(Material::PAINTABLE)
+            .min_by_key(|material| distance(*material))
+            .unwrap()
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Material::Empty => "Empty",
+            Material::Sand => "Sand",
+            Material::Fire => "Fire",
+            Material::Stone => "Stone",
+            Material::Water => "Water",
+            Material::Smoke => "Smoke",
+            Material::Wood => "Wood",
+            Material::Steam => "Steam",
+            Material::Lava => "Lava",
+            Material::Obsidian => "Obsidian",
+            Material::Oil => "Oil",
+            Material::Acid => "Acid",
+            Material::Glass => "Glass",
+            Material::Snow => "Snow",
+        }
+    }
+
+    pub fn color(self) -> (u8, u8, u8, u8) {
+        match self {
+            Material::Empty => (0, 0, 0, 0), //the background shows through
+            Material::Sand => (0, 255, 255, 255),
+            Material::Fire => (255, 255, 0, 255),
+            Material::Stone => (128, 128, 128, 255),
+            Material::Water => (30, 90, 255, 255),
+            Material::Smoke => (180, 180, 180, 200),
+            Material::Wood => (120, 72, 30, 255),
+            Material::Steam => (220, 230, 240, 160),
+            Material::Lava => (255, 90, 0, 255),
+            Material::Obsidian => (40, 20, 50, 255),
+            Material::Oil => (150, 110, 30, 255),
+            Material::Acid => (140, 255, 40, 255),
+            Material::Glass => (200, 230, 255, 90), //whatever is drawn behind the grid shows through
+            Material::Snow => (245, 250, 255, 255),
+        }
+    }
+
+    // Relative weight of a cell, lighter materials are pushed around more easily
+    pub fn density(self) -> f32 {
+        match self {
+            Material::Empty => 0.0,
+            Material::Sand => 1.6,
+            Material::Fire => 0.2,
+            Material::Stone => 2.6,
+            Material::Water => 1.0,
+            Material::Smoke => 0.05,
+            Material::Wood => 0.7,
+            Material::Steam => 0.03,
+            Material::Lava => 3.0,
+            Material::Obsidian => 2.4,
+            Material::Oil => 0.8,
+            Material::Acid => 1.2,
+            Material::Glass => 2.5,
+            Material::Snow => 0.3, //light, so the wind drifts it a lot
+        }
+    }
+
+    // Static materials never move, other cells collide with them
+    pub fn is_static(self) -> bool {
+        matches!(self, Material::Stone | Material::Wood | Material::Obsidian | Material::Glass)
+    }
+
+    // Liquids spread sideways when they can't fall any further
+    pub fn is_liquid(self) -> bool {
+        matches!(self, Material::Water | Material::Lava | Material::Oil | Material::Acid)
+    }
+
+    // Acid eats through dissolvable materials, leaving both cells empty
+    pub fn is_dissolvable(self) -> bool {
+        matches!(self, Material::Stone | Material::Wood | Material::Obsidian)
+    }
+
+    // Gases spread sideways when they can't rise any further
+    pub fn is_gas(self) -> bool {
+        matches!(self, Material::Smoke | Material::Steam)
+    }
+
+    // Rising materials move against gravity
+    pub fn rises(self) -> bool {
+        matches!(self, Material::Fire | Material::Smoke | Material::Steam)
+    }
+
+    // Temperature a cell of this material is held at every tick, None lets it
+    // take the temperature of its surroundings
+    pub fn temperature(self) -> Option<f32> {
+        match self {
+            Material::Fire => Some(800.0),
+            Material::Lava => Some(1200.0),
+            Material::Water => Some(10.0),
+            _ => None,
+        }
+    }
+
+    // Speed falling cells of this material stop speeding up at, None for the
+    // terminal velocity of the grid
+    pub fn terminal_velocity(self) -> Option<f32> {
+        match self {
+            Material::Snow => Some(2.0),
+            _ => None,
+        }
+    }
+
+    // Chance for a blocked cell of this material not to slide diagonally
+    // whatever the repose threshold of the grid, higher gives steeper piles
+    pub fn repose(self) -> f32 {
+        match self {
+            Material::Snow => 0.8,
+            _ => 0.0,
+        }
+    }
+
+    // Temperature above which a cell of this material melts and what into, None
+    // for materials that don't melt. Above the ambient temperature, only cells
+    // next to something hot melt.
+    pub fn melting(self) -> Option<(f32, Material)> {
+        match self {
+            Material::Snow => Some((40.0, Material::Water)),
+            _ => None,
+        }
+    }
+
+    // How much brighter than its color a cell of this material shines in the
+    // HDR color buffer, 1.0 for materials that don't glow
+    pub fn emission(self) -> f32 {
+        match self {
+            Material::Fire => 4.0,
+            Material::Lava => 3.0,
+            _ => 1.0,
+        }
+    }
+
+    // Temperature above which a cell of this material catches fire, None for
+    // materials that don't burn
+    pub fn ignition_temperature(self) -> Option<f32> {
+        match self {
+            Material::Wood => Some(250.0),
+            Material::Oil => Some(200.0),
+            _ => None,
+        }
+    }
+
+    // Number of ticks a cell of this material lives, 0 means forever
+    pub fn lifetime(self) -> u16 {
+        match self {
+            Material::Fire => 90,
+            Material::Smoke => 150,
+            Material::Steam => 120,
+            _ => 0,
+        }
+    }
+
+    // What a cell turns into once its lifetime is over
+    pub fn expires_into(self) -> Material {
+        match self {
+            Material::Fire => Material::Smoke,
+            _ => Material::Empty,
+        }
+    }
+}
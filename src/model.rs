@@ -1,5 +1,5 @@
 use anyhow::Result;
-use glam::{vec2, vec3};
+use glam::{vec2, vec3, Vec2, Vec3};
 use wgpu::util::DeviceExt as _;
 use std::{collections::HashMap, fs::File, io::BufReader};
 
@@ -12,6 +12,8 @@ pub struct Model {
     pub index_buffer: wgpu::Buffer,
     pub meshes: Vec<SubMeshData>,
     pub materials: Vec<Material>,
+    pub cull_back_faces: bool, //closed meshes skip their hidden back faces, flat ones like the sand quad are seen from both sides
+    pub outline: Vec<[Vec2; 3]>, //triangles projected onto the XY plane, to stamp the shape into a grid
 }
 
 pub struct Material {
@@ -67,7 +69,9 @@ impl Model {
             vertex_buffer: vertex_buf, 
             index_buffer: index_buf, 
             meshes: sub_mesh_data, 
-            materials: materials
+            materials,
+            cull_back_faces: false,
+            outline: project_outline(vertices, indices),
          }
     }
 
@@ -82,7 +86,7 @@ impl Model {
 
         let (models, obj_materials) = tobj::load_obj_buf(
             &mut reader,
-            &tobj::LoadOptions { triangulate: true, ..Default::default() },
+            &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() },
             |filename_mtl| {
                 let full_path = get_file_relative_to(filename_mtl, file_path);
                 let file = File::open(full_path).unwrap();
@@ -95,28 +99,14 @@ impl Model {
         for m in obj_materials? {
             let texture_path = get_file_relative_to(std::path::Path::new(&m.diffuse_texture), file_path);
 
-            let texture = texture::Texture::load_texture(device, queue, &texture_path.to_str().unwrap())?;
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                    },
-                ],
-                label: None,
-            });
-            materials.push(Material{
-                name: m.name,
-                diffuse_texture: texture,
-                bind_group: bind_group,
-            });
+            let texture = texture::Texture::load_texture(device, queue, texture_path.to_str().unwrap())?;
+            materials.push(Material::new(device, &m.name, texture, bind_group_layout));
         }
 
+        //meshes without a material use the default one, appended last
+        let default_material = materials.len();
+        let mut needs_default_material = false;
+
         let mut indices = Vec::<u32>::new();
         let mut vertices = Vec::<Vertex>::new();
         let mut sub_mesh_datas = Vec::<SubMeshData>::new();
@@ -124,41 +114,75 @@ impl Model {
 
         for model in &models {
             let index_offset = indices.len();
-            for index in &model.mesh.indices {
-                let pos_offset = (3 * index) as usize;
-                let tex_coord_offset = (2 * index) as usize;
-
-                let vertex = Vertex {
-                    pos: vec3(
+            let has_normals = !model.mesh.normals.is_empty();
+            //shapes exported for their outline often come without texture coordinates
+            let has_tex_coords = !model.mesh.texcoords.is_empty();
+            for triangle in model.mesh.indices.chunks_exact(3) {
+                let triangle = [triangle[0], triangle[1], triangle[2]];
+                let positions = triangle.map(|index| {
+                    let pos_offset = (3 * index) as usize;
+                    vec3(
                         model.mesh.positions[pos_offset],
                         model.mesh.positions[pos_offset + 1],
                         model.mesh.positions[pos_offset + 2],
-                    ),
-                    color: vec3(1.0, 1.0, 1.0),
-                    tex_coord: vec2(
-                        model.mesh.texcoords[tex_coord_offset],
-                        1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                    ),
-                };
+                    )
+                });
+                let face_normal = flat_normal(positions[0], positions[1], positions[2]);
 
-                if let Some(index) = unique_vertices.get(&vertex) {
-                    indices.push(*index as u32);
-                } else {
-                    let index = vertices.len();
-                    unique_vertices.insert(vertex, index);
-                    vertices.push(vertex);
-                    indices.push(index as u32);
+                for (index, pos) in triangle.iter().zip(positions) {
+                    let tex_coord_offset = (2 * index) as usize;
+                    let normal_offset = (3 * index) as usize;
+
+                    let normal = if has_normals {
+                        vec3(
+                            model.mesh.normals[normal_offset],
+                            model.mesh.normals[normal_offset + 1],
+                            model.mesh.normals[normal_offset + 2],
+                        )
+                    } else {
+                        face_normal
+                    };
+
+                    let vertex = Vertex {
+                        pos,
+                        color: vec3(1.0, 1.0, 1.0),
+                        tex_coord: if has_tex_coords {
+                            vec2(
+                                model.mesh.texcoords[tex_coord_offset],
+                                1.0 - model.mesh.texcoords[tex_coord_offset + 1],
+                            )
+                        } else {
+                            Vec2::ZERO
+                        },
+                        normal,
+                    };
+
+                    if let Some(index) = unique_vertices.get(&vertex) {
+                        indices.push(*index as u32);
+                    } else {
+                        let index = vertices.len();
+                        unique_vertices.insert(vertex, index);
+                        vertices.push(vertex);
+                        indices.push(index as u32);
+                    }
                 }
-        
             }
             sub_mesh_datas.push(SubMeshData{
                 name: model.name.clone(),
                 index_offset: index_offset as _,
                 index_count: model.mesh.indices.len(),
-                material: model.mesh.material_id.unwrap(),
+                material: model.mesh.material_id.unwrap_or_else(|| {
+                    needs_default_material = true;
+                    default_material
+                }),
             });
         }
 
+        if needs_default_material {
+            let texture = white_texture(device, queue)?;
+            materials.push(Material::new(device, "Default Material", texture, bind_group_layout));
+        }
+
         let vertex_as_byte_slice = bytemuck::cast_slice(vertices.as_slice());
         let indices_as_byte_slice = bytemuck::cast_slice(indices.as_slice());
 
@@ -179,15 +203,204 @@ impl Model {
             vertex_buffer: vertex_buf, 
             index_buffer: index_buf, 
             meshes: sub_mesh_datas, 
-            materials: materials
+            materials,
+            cull_back_faces: true,
+            outline: project_outline(&vertices, &indices),
          })
     }
 
+    // Loads a .gltf or .glb file, buffers and textures may be embedded or stored
+    // next to it. Every primitive of every mesh becomes a sub mesh, node
+    // transforms are not applied.
+    pub fn load_gltf(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        file_name: &str,
+        bind_group_layout: &wgpu::BindGroupLayout
+    ) -> Result<Self> {
+        let (document, buffers, images) = gltf::import(file_name)?;
+
+        let mut materials = Vec::<Material>::new();
+        for m in document.materials() {
+            let name = m.name().unwrap_or("gltf material");
+            let texture = match m.pbr_metallic_roughness().base_color_texture() {
+                Some(info) => {
+                    let image = &images[info.texture().source().index()];
+                    let pixels = gltf_image_to_rgba(image)?;
+                    texture::Texture::from_bytes(device, queue, image.width as _, image.height as _, &pixels)?
+                }
+                None => white_texture(device, queue)?,
+            };
+            materials.push(Material::new(device, name, texture, bind_group_layout));
+        }
+        //primitives without a material use the default one, appended last
+        let default_material = materials.len();
+        let mut needs_default_material = false;
+
+        let mut indices = Vec::<u32>::new();
+        let mut vertices = Vec::<Vertex>::new();
+        let mut sub_mesh_datas = Vec::<SubMeshData>::new();
+
+        for mesh in document.meshes() {
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let Some(positions) = reader.read_positions() else {
+                    continue;
+                };
+                let positions: Vec<[f32; 3]> = positions.collect();
+                let tex_coords: Vec<[f32; 2]> = reader
+                    .read_tex_coords(0)
+                    .map(|t| t.into_f32().collect())
+                    .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+                let colors: Vec<[f32; 3]> = reader
+                    .read_colors(0)
+                    .map(|c| c.into_rgb_f32().collect())
+                    .unwrap_or_else(|| vec![[1.0, 1.0, 1.0]; positions.len()]);
+                let primitive_indices: Vec<u32> = match reader.read_indices() {
+                    Some(read_indices) => read_indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+
+                let index_offset = indices.len();
+                match reader.read_normals() {
+                    Some(normals) => {
+                        let base_vertex = vertices.len() as u32;
+                        for (((pos, tex_coord), color), normal) in positions.iter().zip(&tex_coords).zip(&colors).zip(normals) {
+                            vertices.push(Vertex {
+                                pos: Vec3::from_array(*pos),
+                                color: Vec3::from_array(*color),
+                                tex_coord: Vec2::from_array(*tex_coord),
+                                normal: Vec3::from_array(normal),
+                            });
+                        }
+                        indices.extend(primitive_indices.iter().map(|i| base_vertex + i));
+                    }
+                    None => {
+                        //flat shading, every triangle gets its own vertices
+                        for triangle in primitive_indices.chunks_exact(3) {
+                            let corners = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+                            let normal = flat_normal(
+                                Vec3::from_array(positions[corners[0]]),
+                                Vec3::from_array(positions[corners[1]]),
+                                Vec3::from_array(positions[corners[2]]));
+                            for i in corners {
+                                indices.push(vertices.len() as u32);
+                                vertices.push(Vertex {
+                                    pos: Vec3::from_array(positions[i]),
+                                    color: Vec3::from_array(colors[i]),
+                                    tex_coord: Vec2::from_array(tex_coords[i]),
+                                    normal,
+                                });
+                            }
+                        }
+                    }
+                }
+
+                let material = primitive.material().index().unwrap_or_else(|| {
+                    needs_default_material = true;
+                    default_material
+                });
+                sub_mesh_datas.push(SubMeshData{
+                    name: mesh.name().unwrap_or("gltf mesh").to_string(),
+                    index_offset,
+                    index_count: indices.len() - index_offset,
+                    material,
+                });
+            }
+        }
+
+        if needs_default_material {
+            let texture = white_texture(device, queue)?;
+            materials.push(Material::new(device, "Default Material", texture, bind_group_layout));
+        }
+
+        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(format!("{file_name} Vertex Buffer").as_str()),
+            contents: bytemuck::cast_slice(vertices.as_slice()),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(format!("{file_name} Index Buffer").as_str()),
+            contents: bytemuck::cast_slice(indices.as_slice()),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Ok(Self {
+            name: file_name.to_string(),
+            vertex_buffer: vertex_buf,
+            index_buffer: index_buf,
+            meshes: sub_mesh_datas,
+            materials,
+            cull_back_faces: true,
+            outline: project_outline(&vertices, &indices),
+        })
+    }
+
     pub fn get_material(&mut self,i:usize) -> &mut Material {
         &mut self.materials[i]
     }
 }
 
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: texture::Texture,
+        bind_group_layout: &wgpu::BindGroupLayout
+    ) -> Self {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+            ],
+            label: None,
+        });
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            bind_group,
+        }
+    }
+}
+
+// Normal of the counter clockwise triangle a, b, c
+fn flat_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    (b - a).cross(c - a).normalize_or_zero()
+}
+
+// Drops the Z of every triangle, what is left is the silhouette seen from the front
+fn project_outline(vertices: &[Vertex], indices: &[u32]) -> Vec<[Vec2; 3]> {
+    indices
+        .chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].pos.truncate()))
+        .collect()
+}
+
+fn white_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<texture::Texture> {
+    texture::Texture::from_bytes(device, queue, 1, 1, &[255, 255, 255, 255])
+}
+
+// Expands the decoded gltf image to the RGBA8 layout textures are created with
+fn gltf_image_to_rgba(image: &gltf::image::Data) -> Result<Vec<u8>> {
+    use gltf::image::Format;
+    let pixels = match image.format {
+        Format::R8G8B8A8 => image.pixels.clone(),
+        Format::R8G8B8 => image.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        Format::R8G8 => image.pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        Format::R8 => image.pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect(),
+        format => anyhow::bail!("unsupported gltf image format {format:?}"),
+    };
+    Ok(pixels)
+}
+
 fn get_file_relative_to(filename_mtl: &std::path::Path , file_path: &std::path::Path) -> std::path::PathBuf {
     let full_path = if let Some(parent) = file_path.parent() {
         parent.join(filename_mtl)
@@ -199,6 +412,9 @@ fn get_file_relative_to(filename_mtl: &std::path::Path , file_path: &std::path::
 
 pub trait ModelDrawer {
     fn draw_model(&mut self, cprojection_bind_group: &wgpu::BindGroup, camera_transform: &wgpu::BindGroup, model: &Model, model_transform: &wgpu::BindGroup);
+    // Draws `instance_count` copies of the model, `instance_buffer` holds one Mat4 per
+    // instance applied before `model_transform`. Needs a pipeline using the instance layout.
+    fn draw_model_instanced(&mut self, projection_bind_group: &wgpu::BindGroup, camera_transform: &wgpu::BindGroup, model: &Model, model_transform: &wgpu::BindGroup, instance_buffer: &wgpu::Buffer, instance_count: u32);
 }
 
 impl<'rp> ModelDrawer for wgpu::RenderPass<'rp>{
@@ -213,7 +429,20 @@ impl<'rp> ModelDrawer for wgpu::RenderPass<'rp>{
         self.set_vertex_buffer(0, model.vertex_buffer.slice(..));
         for meshdata in &model.meshes  {
             self.set_bind_group(3, &model.materials[meshdata.material].bind_group, &[]);
-            self.draw_indexed(meshdata.index_offset as _ ..meshdata.index_count as u32, 0, 0..1);
+            self.draw_indexed(meshdata.index_offset as u32..(meshdata.index_offset + meshdata.index_count) as u32, 0, 0..1);
+        }
+    }
+
+    fn draw_model_instanced(&mut self, projection_bind_group: &wgpu::BindGroup, camera_transform: &wgpu::BindGroup, model: &Model, model_transform: &wgpu::BindGroup, instance_buffer: &wgpu::Buffer, instance_count: u32) {
+        self.set_bind_group(0, projection_bind_group, &[]);
+        self.set_bind_group(1, camera_transform, &[]);
+        self.set_bind_group(2, model_transform, &[]);
+        self.set_index_buffer(model.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_vertex_buffer(0, model.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, instance_buffer.slice(..));
+        for meshdata in &model.meshes  {
+            self.set_bind_group(3, &model.materials[meshdata.material].bind_group, &[]);
+            self.draw_indexed(meshdata.index_offset as u32..(meshdata.index_offset + meshdata.index_count) as u32, 0, 0..instance_count);
         }
     }
 }
\ No newline at end of file
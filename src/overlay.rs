@@ -0,0 +1,127 @@
+use rust_sand_sim::cpu_texture::CpuTexture;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const GLYPH_SPACING: usize = 1;
+const LINE_SPACING: usize = 2;
+
+const TEXT_COLOR: (u8, u8, u8, u8) = (255, 255, 255, 255);
+const BACKGROUND_COLOR: (u8, u8, u8, u8) = (0, 0, 0, 160);
+
+// Text drawn with a 5x7 bitmap font into a CPU texture, meant to be shown on a
+// quad over the scene
+pub struct TextOverlay {
+    texture: CpuTexture,
+}
+
+impl TextOverlay {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            texture: CpuTexture::new(width, height, vec![0; width * height * 4]),
+        }
+    }
+
+    pub fn texture(&self) -> &CpuTexture {
+        &self.texture
+    }
+
+    // Replaces the content of the overlay with `lines`, clipped to the texture
+    pub fn set_lines(&mut self, lines: &[String]) {
+        let width = self.texture.get_width();
+        let height = self.texture.get_height();
+        for y in 0..height {
+            for x in 0..width {
+                self.texture.set_pixel(x, y, 0, 0, 0, 0);
+            }
+        }
+
+        for (line_index, line) in lines.iter().enumerate() {
+            let y0 = LINE_SPACING + line_index * (GLYPH_HEIGHT + LINE_SPACING);
+            let line_width = line.chars().count() * (GLYPH_WIDTH + GLYPH_SPACING) + GLYPH_SPACING;
+
+            //darken the area behind the line so it reads over any grid content
+            for y in y0.saturating_sub(1)..std::cmp::min(y0 + GLYPH_HEIGHT + 1, height) {
+                for x in 0..std::cmp::min(line_width + 1, width) {
+                    let (r, g, b, a) = BACKGROUND_COLOR;
+                    self.texture.set_pixel(x, y, r, g, b, a);
+                }
+            }
+
+            for (char_index, c) in line.chars().enumerate() {
+                let x0 = GLYPH_SPACING + 1 + char_index * (GLYPH_WIDTH + GLYPH_SPACING);
+                self.draw_glyph(x0, y0, c);
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, x0: usize, y0: usize, c: char) {
+        let rows = glyph(c);
+        for (dy, row) in rows.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let x = x0 + dx;
+                let y = y0 + dy;
+                if x >= self.texture.get_width() || y >= self.texture.get_height() {
+                    continue;
+                }
+                if row & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+                    let (r, g, b, a) = TEXT_COLOR;
+                    self.texture.set_pixel(x, y, r, g, b, a);
+                }
+            }
+        }
+    }
+}
+
+// Rows of the glyph, top to bottom, the 5 low bits of each row are the pixels.
+// Lowercase letters use the uppercase glyphs and unknown characters are blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
@@ -0,0 +1,94 @@
+use rust_sand_sim::material::Material;
+use rust_sand_sim::sand_grid::{BoundaryMode, SandGrid};
+
+// Name of a starting scene and the function building it on a grid
+type Preset = (&'static str, fn(&mut SandGrid));
+
+// Starting scenes picked with --preset, built with the scene API so they are the
+// same on every run. They scale with the grid, sizes are fractions of it.
+pub const PRESETS: [Preset; 3] = [("hourglass", hourglass), ("dam", dam), ("funnel", funnel)];
+
+pub fn find(name: &str) -> Option<fn(&mut SandGrid)> {
+    PRESETS.iter().find(|(preset, _)| *preset == name).map(|(_, build)| *build)
+}
+
+// Names of all the presets, for error messages
+pub fn names() -> String {
+    PRESETS.map(|(name, _)| name).join(", ")
+}
+
+// Point at the fractions (fx, fy) of the grid
+fn at(grid: &SandGrid, fx: f32, fy: f32) -> (isize, isize) {
+    ((grid.width as f32 * fx) as isize, (grid.height as f32 * fy) as isize)
+}
+
+// Stone line 2 cells wide, grains slip through the corners of a 1 cell wide diagonal
+fn wall(grid: &mut SandGrid, a: (isize, isize), b: (isize, isize)) {
+    grid.draw_line(a, b, Material::Stone);
+    grid.draw_line((a.0 + 1, a.1), (b.0 + 1, b.1), Material::Stone);
+}
+
+// Fills the rows from `top` to `bottom` between the walls along the lines
+// `left` and `right`, both given as their two ends
+fn fill_between(grid: &mut SandGrid, left: [(isize, isize); 2], right: [(isize, isize); 2], top: isize, bottom: isize, material: Material) {
+    let x_on = |[a, b]: [(isize, isize); 2], y: isize| {
+        if a.1 == b.1 {
+            return a.0;
+        }
+        a.0 + (b.0 - a.0) * (y - a.1) / (b.1 - a.1)
+    };
+    for y in top.max(0)..bottom {
+        //leaves the 2 cells of the walls alone
+        let x0 = (x_on(left, y) + 2).max(0);
+        let x1 = x_on(right, y);
+        if x1 > x0 {
+            grid.fill_rect(x0 as usize, y as usize, (x1 - x0) as usize, 1, material);
+        }
+    }
+}
+
+// Two stone bulbs meeting at a narrow neck, the top one full of sand
+fn hourglass(grid: &mut SandGrid) {
+    let neck = std::cmp::max(2, grid.width / 64) as isize;
+    let (top_left, top_right) = (at(grid, 0.25, 0.05), at(grid, 0.75, 0.05));
+    let (bottom_left, bottom_right) = (at(grid, 0.25, 0.95), at(grid, 0.75, 0.95));
+    let center = at(grid, 0.5, 0.5);
+    let (neck_left, neck_right) = ((center.0 - neck - 2, center.1), (center.0 + neck, center.1));
+
+    wall(grid, top_left, top_right);
+    wall(grid, top_left, neck_left);
+    wall(grid, top_right, neck_right);
+    wall(grid, neck_left, bottom_left);
+    wall(grid, neck_right, bottom_right);
+    wall(grid, bottom_left, bottom_right);
+    fill_between(grid, [top_left, neck_left], [top_right, neck_right], top_left.1 + 2, at(grid, 0.0, 0.35).1, Material::Sand);
+}
+
+// A lake of water held back by a stone dam with a breach at its foot
+fn dam(grid: &mut SandGrid) {
+    let floor = at(grid, 0.0, 0.9).1 as usize;
+    let dam_x = at(grid, 0.4, 0.0).0 as usize;
+    let dam_width = std::cmp::max(2, grid.width / 40);
+    let breach = std::cmp::max(2, grid.height / 60);
+    let dam_top = at(grid, 0.0, 0.3).1 as usize;
+    let bank = at(grid, 0.0, 0.8).1 as usize;
+
+    grid.fill_rect(0, floor, grid.width, grid.height - floor, Material::Stone);
+    grid.fill_rect(dam_x, dam_top, dam_width, floor.saturating_sub(dam_top + breach), Material::Stone);
+    grid.fill_rect(0, dam_top + dam_width, dam_x, floor.saturating_sub(dam_top + dam_width), Material::Water);
+    //a sand bank downstream for the flood to wash into
+    grid.fill_rect(at(grid, 0.7, 0.0).0 as usize, bank, grid.width / 5, floor - bank, Material::Sand);
+}
+
+// Sand pouring through a stone funnel out of the bottom of the grid, which is open
+fn funnel(grid: &mut SandGrid) {
+    let neck = std::cmp::max(2, grid.width / 32) as isize;
+    let (rim_left, rim_right) = (at(grid, 0.1, 0.3), at(grid, 0.9, 0.3));
+    let center = at(grid, 0.5, 0.7);
+    let (neck_left, neck_right) = ((center.0 - neck - 2, center.1), (center.0 + neck, center.1));
+
+    wall(grid, rim_left, neck_left);
+    wall(grid, rim_right, neck_right);
+    fill_between(grid, [rim_left, neck_left], [rim_right, neck_right], rim_left.1 + 2, at(grid, 0.0, 0.55).1, Material::Sand);
+    grid.boundary = BoundaryMode::FUNNEL;
+}
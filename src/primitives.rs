@@ -1,5 +1,7 @@
 use glam::{Vec2, Vec3};
 
+use rust_sand_sim::cpu_texture::CpuTexture;
+
 use crate::{model, texture, utils::Vertex, Model};
 
 pub struct Quad {
@@ -7,6 +9,7 @@ pub struct Quad {
 }
 
 impl Quad {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(device: &wgpu::Device, size: &Vec2, material: model::Material) -> Model {
         let vertices = vec![
             Vertex::new(Vec3::new(0.0,    0.0, 0.0   ), Vec2::new(0.0, 0.0)),
@@ -26,44 +29,36 @@ impl Quad {
     }
 }
 
-pub struct CpuTexture {
-    width: usize,
-    height: usize,
-    data: Vec<u8>,
-}
-
-impl CpuTexture {
-    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Self {
-        Self { width, height, data }
-    }
-
-    pub fn get_width(&self) -> usize {
-        self.width
-    }
+// Border of `thickness` around the size.x x size.y rectangle a Quad of that size covers
+pub struct Frame {
 
-    pub fn get_height(&self) -> usize {
-        self.height
-    }
+}
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, r:u8, g:u8, b:u8, a:u8) {
-        let i = ((y * self.width  + x) * 4) as usize;
-        self.data[i + 0] = r;
-        self.data[i + 1] = g;
-        self.data[i + 2] = b;
-        self.data[i + 3] = a;
-    }
+impl Frame {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(device: &wgpu::Device, size: &Vec2, thickness: f32, material: model::Material) -> Model {
+        let (x0, z0) = (-thickness, -thickness);
+        let (x1, z1) = (size.x + thickness, size.y + thickness);
+        //one rectangle per side, the top and bottom ones span the corners
+        let sides = [
+            (x0,     z0,     x1,     0.0   ),
+            (x0,     size.y, x1,     z1    ),
+            (x0,     0.0,    0.0,    size.y),
+            (size.x, 0.0,    x1,     size.y),
+        ];
 
-    pub fn get_pixels(&self) -> &Vec<u8> {
-        &self.data
-    }
+        let mut vertices = Vec::with_capacity(sides.len() * 4);
+        let mut indices = Vec::with_capacity(sides.len() * 6);
+        for (left, top, right, bottom) in sides {
+            let first = vertices.len() as u32;
+            vertices.push(Vertex::new(Vec3::new(left,  0.0, top   ), Vec2::new(0.0, 0.0)));
+            vertices.push(Vertex::new(Vec3::new(left,  0.0, bottom), Vec2::new(0.0, 1.0)));
+            vertices.push(Vertex::new(Vec3::new(right, 0.0, bottom), Vec2::new(1.0, 1.0)));
+            vertices.push(Vertex::new(Vec3::new(right, 0.0, top   ), Vec2::new(1.0, 0.0)));
+            indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
 
-    pub fn get_pixel(&self,x: usize, y: usize) -> (u8,u8,u8,u8) {
-        let i = ((y * self.width  + x) * 4) as usize;
-        let r = self.data[i + 0];
-        let g = self.data[i + 1];
-        let b = self.data[i + 2];
-        let a = self.data[i + 3];
-        (r,g,b,a)
+        Model::new(device, "Frame", &vertices, &indices, material)
     }
 }
 
@@ -75,30 +70,16 @@ pub fn create_white_material(device: &wgpu::Device, queue: &wgpu::Queue, bind_gr
         255,255,255,255,
     ];
     let cpu_texture = CpuTexture::new(2,2,texture_data);
-    create_custom_tex_material(device, queue, bind_group_layout, &cpu_texture)
+    create_custom_tex_material(device, queue, bind_group_layout, &cpu_texture, &Default::default())
 }
 
-pub fn create_custom_tex_material(device: &wgpu::Device, queue: &wgpu::Queue, bind_group_layout: &wgpu::BindGroupLayout, cpu_texture: &CpuTexture ) -> model::Material {
-
-        let texture = texture::Texture::from_bytes(device, queue, cpu_texture.width,cpu_texture.height, &cpu_texture.data).expect("Unable to create white texture");
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-            label: None,
-        });
-        let diffuse_texture = texture;
-        crate::model::Material{
-            name: "White Material".to_string(),
-            diffuse_texture,
-            bind_group,
-        }
+pub fn create_color_material(device: &wgpu::Device, queue: &wgpu::Queue, bind_group_layout: &wgpu::BindGroupLayout, r: u8, g: u8, b: u8) -> model::Material {
+    let cpu_texture = CpuTexture::new(1,1,vec![r, g, b, 255]);
+    create_custom_tex_material(device, queue, bind_group_layout, &cpu_texture, &Default::default())
+}
+
+pub fn create_custom_tex_material(device: &wgpu::Device, queue: &wgpu::Queue, bind_group_layout: &wgpu::BindGroupLayout, cpu_texture: &CpuTexture, options: &texture::TextureOptions ) -> model::Material {
+
+        let texture = texture::Texture::from_bytes_with_options(device, queue, cpu_texture.get_width(), cpu_texture.get_height(), cpu_texture.get_pixels(), options).expect("Unable to create white texture");
+        crate::model::Material::new(device, "White Material", texture, bind_group_layout)
 }
\ No newline at end of file
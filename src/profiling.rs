@@ -0,0 +1,43 @@
+// Per frame timing spans. Built with `--features profiling` every span is
+// recorded with `tracing` and written to a chrome trace (trace-<timestamp>.json,
+// open it in chrome://tracing or ui.perfetto.dev) when the app exits. Without the
+// feature the spans compile to nothing.
+
+// Times the rest of the enclosing block as a span called `$name`
+#[cfg(feature = "profiling")]
+macro_rules! span {
+    ($name:literal) => {
+        let _span = tracing::info_span!($name).entered();
+    };
+}
+
+#[cfg(not(feature = "profiling"))]
+macro_rules! span {
+    ($name:literal) => {};
+}
+
+pub(crate) use span;
+
+// Keeps the trace file open, it is flushed when dropped
+#[cfg(feature = "profiling")]
+pub struct Profiler {
+    _guard: tracing_chrome::FlushGuard,
+}
+
+#[cfg(not(feature = "profiling"))]
+pub struct Profiler;
+
+// Starts recording spans, keep the result alive until the app exits
+#[cfg(feature = "profiling")]
+pub fn init() -> Profiler {
+    use tracing_subscriber::prelude::*;
+
+    let (chrome_layer, guard) = tracing_chrome::ChromeLayerBuilder::new().include_args(true).build();
+    tracing_subscriber::registry().with(chrome_layer).init();
+    Profiler { _guard: guard }
+}
+
+#[cfg(not(feature = "profiling"))]
+pub fn init() -> Profiler {
+    Profiler
+}
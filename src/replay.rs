@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use anyhow::Context;
+use glam::Vec2;
+use rust_sand_sim::material::Material;
+use rust_sand_sim::sand_grid::{BoundaryMode, BrushShape, EdgeMode};
+
+// Something the user did to the grid during a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridEvent {
+    Spawn { x: usize, y: usize, material: Material, shape: BrushShape },
+    Erase { x: usize, y: usize, shape: BrushShape },
+    Line { from: (usize, usize), to: (usize, usize), material: Material }, //Empty erases
+    Fill { x: usize, y: usize, material: Material },
+    Emitter { x: usize, y: usize, material: Material },
+    Attractor { x: usize, y: usize },
+    Freeze { x: usize, y: usize, width: usize, height: usize, frozen: bool },
+    ClearAttractors,
+    Clear, //empties every cell
+    Undo,
+    Redo,
+}
+
+// Simulation settings in effect for a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameSettings {
+    pub gravity: Vec2,
+    pub wind: f32,
+    pub repose_threshold: f32,
+    pub heat_enabled: bool,
+    pub parallel_simulation: bool,
+    pub boundary: BoundaryMode,
+}
+
+// Everything needed to replay a frame: its dt, the settings and the grid
+// events, applied in order before the frame is simulated
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+    pub dt: f32,
+    pub settings: FrameSettings,
+    pub events: Vec<GridEvent>,
+}
+
+// Text file with one line per frame followed by one line per event of that frame:
+//   frame <dt> <gravity x> <gravity y> <wind> <repose threshold> <heat 0|1> <parallel 0|1> [<edges>]
+//   spawn <x> <y> <material id> [<brush>]
+//   erase <x> <y> [<brush>]
+//   line <x> <y> <to x> <to y> <material id>
+//   fill <x> <y> <material id>
+//   emitter <x> <y> <material id>
+//   attractor <x> <y>
+//   freeze <x> <y> <width> <height> <frozen 0|1>
+//   clear_attractors
+//   clear
+//   undo
+//   redo
+// <brush> is `square <side>` or `circle <radius>`, recordings made before brush
+// shapes existed don't have it and paint with `BrushShape::DEFAULT`.
+// <edges> has one digit per edge, left, top, right and bottom, 0 for solid, 1
+// for wrapping and 2 for open. A single digit sets all four.
+// Floats are written in their shortest round trip form so replays are exact.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> anyhow::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(Self { writer })
+    }
+
+    pub fn write_frame(&mut self, frame: &FrameRecord) -> anyhow::Result<()> {
+        let settings = &frame.settings;
+        writeln!(
+            self.writer,
+            "frame {} {} {} {} {} {} {} {}{}{}{}",
+            frame.dt,
+            settings.gravity.x,
+            settings.gravity.y,
+            settings.wind,
+            settings.repose_threshold,
+            settings.heat_enabled as u8,
+            settings.parallel_simulation as u8,
+            edge_code(settings.boundary.edges[BoundaryMode::LEFT]),
+            edge_code(settings.boundary.edges[BoundaryMode::TOP]),
+            edge_code(settings.boundary.edges[BoundaryMode::RIGHT]),
+            edge_code(settings.boundary.edges[BoundaryMode::BOTTOM]))?;
+        for event in &frame.events {
+            match event {
+                GridEvent::Spawn { x, y, material, shape } => writeln!(self.writer, "spawn {x} {y} {} {}", material.id(), brush_text(*shape))?,
+                GridEvent::Erase { x, y, shape } => writeln!(self.writer, "erase {x} {y} {}", brush_text(*shape))?,
+                GridEvent::Line { from, to, material } => writeln!(self.writer, "line {} {} {} {} {}", from.0, from.1, to.0, to.1, material.id())?,
+                GridEvent::Fill { x, y, material } => writeln!(self.writer, "fill {x} {y} {}", material.id())?,
+                GridEvent::Emitter { x, y, material } => writeln!(self.writer, "emitter {x} {y} {}", material.id())?,
+                GridEvent::Attractor { x, y } => writeln!(self.writer, "attractor {x} {y}")?,
+                GridEvent::Freeze { x, y, width, height, frozen } => writeln!(self.writer, "freeze {x} {y} {width} {height} {}", *frozen as u8)?,
+                GridEvent::ClearAttractors => writeln!(self.writer, "clear_attractors")?,
+                GridEvent::Clear => writeln!(self.writer, "clear")?,
+                GridEvent::Undo => writeln!(self.writer, "undo")?,
+                GridEvent::Redo => writeln!(self.writer, "redo")?,
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Hands out the frames of a recording one at a time
+pub struct Replay {
+    frames: std::vec::IntoIter<FrameRecord>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut frames = Vec::<FrameRecord>::new();
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            parse_line(&line, &mut frames).with_context(|| format!("{path}:{}: invalid line '{line}'", line_number + 1))?;
+        }
+        Ok(Self { frames: frames.into_iter() })
+    }
+
+    pub fn next_frame(&mut self) -> Option<FrameRecord> {
+        self.frames.next()
+    }
+}
+
+fn brush_text(shape: BrushShape) -> String {
+    match shape {
+        BrushShape::Square(side) => format!("square {side}"),
+        BrushShape::Circle(radius) => format!("circle {radius}"),
+    }
+}
+
+fn parse_brush<'a>(words: &mut impl Iterator<Item = &'a str>) -> anyhow::Result<BrushShape> {
+    let Some(kind) = words.next() else {
+        return Ok(BrushShape::DEFAULT);
+    };
+    let size = words.next().context("missing brush size")?.parse()?;
+    match kind {
+        "square" => Ok(BrushShape::Square(size)),
+        "circle" => Ok(BrushShape::Circle(size)),
+        _ => anyhow::bail!("unknown brush '{kind}'"),
+    }
+}
+
+fn edge_code(mode: EdgeMode) -> u8 {
+    match mode {
+        EdgeMode::Solid => 0,
+        EdgeMode::Wrap => 1,
+        EdgeMode::Open => 2,
+    }
+}
+
+fn parse_edges(edges: &str) -> anyhow::Result<BoundaryMode> {
+    let modes = edges
+        .chars()
+        .map(|code| match code {
+            '0' => Ok(EdgeMode::Solid),
+            '1' => Ok(EdgeMode::Wrap),
+            '2' => Ok(EdgeMode::Open),
+            _ => anyhow::bail!("unknown edge mode '{code}'"),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    match modes[..] {
+        [mode] => Ok(BoundaryMode::uniform(mode)),
+        [left, top, right, bottom] => Ok(BoundaryMode { edges: [left, top, right, bottom] }),
+        _ => anyhow::bail!("expected 1 or 4 edge modes, got '{edges}'"),
+    }
+}
+
+fn parse_line(line: &str, frames: &mut Vec<FrameRecord>) -> anyhow::Result<()> {
+    let mut words = line.split_whitespace();
+    let Some(kind) = words.next() else {
+        return Ok(());
+    };
+    let mut next = || words.next().context("missing value");
+
+    if kind == "frame" {
+        let dt = next()?.parse()?;
+        let gravity = Vec2::new(next()?.parse()?, next()?.parse()?);
+        let wind = next()?.parse()?;
+        let repose_threshold = next()?.parse()?;
+        let heat_enabled = next()?.parse::<u8>()? != 0;
+        let parallel_simulation = next()?.parse::<u8>()? != 0;
+        //recordings made before wrapping edges existed don't have it, the ones
+        //made before per edge modes have a single digit
+        let boundary = match words.next() {
+            Some(edges) => parse_edges(edges)?,
+            None => BoundaryMode::SOLID,
+        };
+        frames.push(FrameRecord {
+            dt,
+            settings: FrameSettings { gravity, wind, repose_threshold, heat_enabled, parallel_simulation, boundary },
+            events: Vec::new(),
+        });
+        return Ok(());
+    }
+
+    let event = match kind {
+        "clear_attractors" => GridEvent::ClearAttractors,
+        "clear" => GridEvent::Clear,
+        "undo" => GridEvent::Undo,
+        "redo" => GridEvent::Redo,
+        _ => {
+            let x = next()?.parse()?;
+            let y = next()?.parse()?;
+            match kind {
+                "spawn" => GridEvent::Spawn { x, y, material: Material::from_id(next()?.parse()?), shape: parse_brush(&mut words)? },
+                "erase" => GridEvent::Erase { x, y, shape: parse_brush(&mut words)? },
+                "line" => GridEvent::Line { from: (x, y), to: (next()?.parse()?, next()?.parse()?), material: Material::from_id(next()?.parse()?) },
+                "fill" => GridEvent::Fill { x, y, material: Material::from_id(next()?.parse()?) },
+                "emitter" => GridEvent::Emitter { x, y, material: Material::from_id(next()?.parse()?) },
+                "attractor" => GridEvent::Attractor { x, y },
+                "freeze" => GridEvent::Freeze {
+                    x,
+                    y,
+                    width: next()?.parse()?,
+                    height: next()?.parse()?,
+                    frozen: next()?.parse::<u8>()? != 0,
+                },
+                _ => anyhow::bail!("unknown record '{kind}'"),
+            }
+        }
+    };
+    frames.last_mut().context("event before the first frame")?.events.push(event);
+    Ok(())
+}
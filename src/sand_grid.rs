@@ -0,0 +1,2730 @@
+use anyhow::Context;
+use glam::Vec2;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+use crate::behavior::{self, CellContext, ParticleBehavior};
+use crate::cpu_texture::{self, CpuTexture, HdrTexture};
+use crate::material::{Material, ReactionResult};
+
+const SAVE_FILE_MAGIC: &[u8; 4] = b"SAND";
+// Side of the square chunks used to skip settled regions of the grid
+const CHUNK_SIZE: usize = 16;
+// Temperature of the grid before anything heats or cools it
+const AMBIENT_TEMPERATURE: f32 = 20.0;
+// Opacity of a fresh trail and how much of it fades every simulate call
+const TRAIL_ALPHA: u8 = 96;
+const TRAIL_DECAY: u8 = 16;
+// Ticks a grain has to rest on a full base before it stops trying to slide
+const SETTLE_TICKS: u8 = 8;
+// Chance for an acid cell to dissolve a neighbour each tick
+const ACID_DISSOLVE_CHANCE: f64 = 0.05;
+// Wetness sand gains every tick next to water and loses every tick away from it
+const WETTING_RATE: f32 = 0.1;
+const DRYING_RATE: f32 = 0.005;
+// Share of the slides left by `repose_threshold` that soaked sand resists
+const WET_REPOSE: f32 = 0.9;
+// The density color mode counts the filled cells in the square this many cells
+// around each cell
+const DENSITY_RADIUS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Material, //base color of the material
+    Velocity, //heatmap of the grain velocities
+    Temperature, //cold cells blue, hot ones red to yellow
+    Wetness, //dry cells black, soaked ones blue
+    Density, //fraction of filled cells around each cell, black for voids to pale yellow for packed piles
+}
+
+pub struct SandGrid {
+    pub width: usize,
+    pub height: usize,
+    meta: Vec<u8>, //occupied or not, but could be expanded in the future to include other metadata
+    pub color: CpuTexture,
+    hdr: Option<HdrTexture>, //linear copy of `color` with glowing materials brighter, see `set_hdr`
+    velocity: Vec<Vec2>,
+    lifetime: Vec<u16>, //remaining ticks of cells whose material expires
+    temperature: Vec<f32>, //moves along with the cells and diffuses when `heat_enabled`
+    shade: Vec<f32>, //random brightness of each cell in [-1, 1], scaled by `color_jitter`
+    trail: Vec<(u8, u8, u8, u8)>, //tint left on empty cells by fast grains, alpha fades out in `decay_trails`
+    settle: Vec<u8>, //ticks each grain has rested on a full base, see `update_settle`
+    age: Vec<u16>, //ticks each grain rested settled, only counted while `aging` is on and up to the end of `aging_colormap`, see `update_aging`
+    aging: bool,
+    aging_colormap: Vec<(f32, [u8; 3])>, //age in ticks and the color multiplying the material color by increasing age
+    wetness: Vec<f32>, //from 0 for dry to 1 for soaked, moves along with the cells, see `update_wetness`
+    wetness_changing: bool, //some sand got wetter or drier, or cells changed, since the last `update_wetness`
+    pub color_jitter: f32, //largest relative brightness change between two cells of the same material
+    pub trails_enabled: bool, //grains moving several cells in one step leave a trail
+    trails_fading: bool, //some trail hasn't faded out yet
+    pub gravity: Vec2, //acceleration of falling cells, its length is how fast they speed up
+    pub boundary: BoundaryMode,
+    pub max_velocity: f32, //terminal velocity, fast grains on tall grids stop speeding up there
+    pub heat_enabled: bool, //runs the heat diffusion and ignition pass, see `update_heat`
+    reactions: HashMap<(Material, Material), ReactionResult>,
+    behaviors: HashMap<Material, Arc<dyn ParticleBehavior>>, //replace the built in behavior of their material, see `set_behavior`
+    moved_cells: usize, //moves made during the current simulate call, see `stats`
+    drained_cells: usize, //cells removed by drains and open edges during the current simulate call
+    drain: Vec<bool>, //cells removing whatever moves into them, see `set_drain`
+    frozen: Vec<bool>, //cells left out of the simulation, see `set_frozen`
+    passes: Vec<u32>, //cells moved into each cell so far, wrapping, see `pass_count`
+    grain_delta: isize, //grains created minus grains destroyed by reactions during the current simulate call
+    report: Option<TickReport>, //changes made by the `tick` in progress, None outside of one
+    pub emitters: Vec<Emitter>,
+    pub attractors: Vec<Attractor>,
+    pub wind: f32, //sideways push on moving grains, positive to the right of gravity, see `drift`
+    color_mode: ColorMode,
+    velocity_colormap: Vec<(f32, [u8; 3])>, //speed and color pairs by increasing speed, see `velocity_to_color`
+    pub repose_threshold: f32, //chance for a blocked grain not to slide diagonally, higher gives steeper piles
+    pub max_cells_per_step: usize, //fast grains are simulated in sub steps to respect it
+    rng: StdRng,
+    dirty: Option<DirtyRect>, //region of `color` changed since the last upload
+    active_chunks: Vec<bool>, //chunks simulated by the current step
+    woken_chunks: Vec<bool>, //chunks to simulate in the next step, something changed in or next to them
+    aged_chunks: Vec<bool>, //chunks whose grains all reached the end of `aging_colormap`, skipped by `update_aging`
+    chunk_columns: usize,
+    chunk_origin_x: usize, //column of the full grid a band starts at, bands share its chunks
+    settled_gravity: Vec2, //gravity the chunks were put to sleep under
+}
+
+// Spawns a cell of `material` next to (x, y), along gravity, every `interval`
+// ticks as long as that cell is empty
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Emitter {
+    pub x: usize,
+    pub y: usize,
+    pub material: Material,
+    pub interval: u32,
+    countdown: u32,
+}
+
+impl Emitter {
+    pub fn new(x: usize, y: usize, material: Material, interval: u32) -> Self {
+        Self { x, y, material, interval: std::cmp::max(1, interval), countdown: 0 }
+    }
+}
+
+// Pulls falling cells towards (x, y) with an acceleration of strength / distance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attractor {
+    pub x: f32,
+    pub y: f32,
+    pub strength: f32,
+}
+
+// Movement counters of the last `simulate` call
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub moved: usize, //moves made by cells, a cell moving in several sub steps counts once per move
+    pub resting: usize, //cells that could move but didn't, static materials aren't counted
+    pub drained: usize, //cells removed by drains and open edges, the outflow of the grid
+}
+
+// A cell moved by a tick from one position to another, swapping places with
+// whatever was there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovedCell {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub material: Material,
+}
+
+// Two neighbour cells that reacted during a tick, `a` at `cell` and `b` at
+// `neighbour` before, the materials of `result` after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reaction {
+    pub cell: (usize, usize),
+    pub neighbour: (usize, usize),
+    pub a: Material,
+    pub b: Material,
+    pub result: ReactionResult,
+}
+
+// Changes made by `SandGrid::tick`, each list in the order things happened
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TickReport {
+    pub moved: Vec<MovedCell>, //a cell moving in several sub steps is listed once per move
+    pub reactions: Vec<Reaction>, //acid dissolving a cell too, into two empty cells
+}
+
+// Copy of the cells of a rectangle of the grid, see `SandGrid::copy_region`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GridRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    meta: Vec<u8>,
+    velocity: Vec<Vec2>,
+    lifetime: Vec<u16>,
+    temperature: Vec<f32>,
+    shade: Vec<f32>,
+    wetness: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    fn from_point(x: usize, y: usize) -> Self {
+        DirtyRect { x, y, width: 1, height: 1 }
+    }
+
+    fn include(&mut self, x: usize, y: usize) {
+        let x1 = std::cmp::max(self.x + self.width, x + 1);
+        let y1 = std::cmp::max(self.y + self.height, y + 1);
+        self.x = std::cmp::min(self.x, x);
+        self.y = std::cmp::min(self.y, y);
+        self.width = x1 - self.x;
+        self.height = y1 - self.y;
+    }
+}
+
+// Cells painted around the cursor by `spawn_shape_at` and `erase_shape_at`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Square(usize), //side in cells, for an even side the cursor is right of and below the middle
+    Circle(usize), //radius in cells, the cursor cell alone for 0
+}
+
+impl BrushShape {
+    // The brush of `spawn_material_at` and `erase_at`
+    pub const DEFAULT: Self = Self::Square(2 * SandGrid::BRUSH_RADIUS);
+
+    // Offsets from the cursor of the cells under the brush, row by row from the top
+    pub fn offsets(self) -> impl Iterator<Item = (isize, isize)> {
+        let (low, high) = self.bounds();
+        (low..high).flat_map(move |dy| (low..high).map(move |dx| (dx, dy))).filter(move |&(dx, dy)| self.covers(dx, dy))
+    }
+
+    // Offsets along each axis the brush spans, from `.0` included to `.1` excluded
+    pub fn bounds(self) -> (isize, isize) {
+        match self {
+            Self::Square(side) => (-(side as isize / 2), side as isize - side as isize / 2),
+            Self::Circle(radius) => (-(radius as isize), radius as isize + 1),
+        }
+    }
+
+    pub fn covers(self, dx: isize, dy: isize) -> bool {
+        let (low, high) = self.bounds();
+        if !(low..high).contains(&dx) || !(low..high).contains(&dy) {
+            return false;
+        }
+        match self {
+            Self::Square(_) => true,
+            Self::Circle(radius) => dx * dx + dy * dy <= (radius * radius) as isize,
+        }
+    }
+}
+
+// What happens to cells reaching an edge of the grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeMode {
+    Solid, //blocks cells like a wall
+    Wrap, //cells leaving through it come back through the opposite edge
+    Open, //cells leaving through it are removed, like drains
+}
+
+// Mode of each edge of the grid, see `BoundaryMode::LEFT` and the others for
+// which is which
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundaryMode {
+    pub edges: [EdgeMode; 4],
+}
+
+impl BoundaryMode {
+    pub const LEFT: usize = 0;
+    pub const TOP: usize = 1;
+    pub const RIGHT: usize = 2;
+    pub const BOTTOM: usize = 3;
+    pub const SOLID: Self = Self::uniform(EdgeMode::Solid);
+    pub const WRAP: Self = Self::uniform(EdgeMode::Wrap);
+    // Solid sides, grains pour out through the bottom
+    pub const FUNNEL: Self = Self { edges: [EdgeMode::Solid, EdgeMode::Solid, EdgeMode::Solid, EdgeMode::Open] };
+
+    pub const fn uniform(mode: EdgeMode) -> Self {
+        Self { edges: [mode; 4] }
+    }
+
+    pub fn wraps(&self) -> bool {
+        self.edges.contains(&EdgeMode::Wrap)
+    }
+
+    // Edge crossed going from (x, y) to (x + dx, y + dy) on a grid of `width` x
+    // `height`, None when that cell is inside
+    fn crossed_edge(width: usize, height: usize, x: usize, y: usize, dx: isize, dy: isize) -> Option<usize> {
+        let (x, y) = (x as isize + dx, y as isize + dy);
+        if x < 0 {
+            Some(Self::LEFT)
+        } else if x >= width as isize {
+            Some(Self::RIGHT)
+        } else if y < 0 {
+            Some(Self::TOP)
+        } else if y >= height as isize {
+            Some(Self::BOTTOM)
+        } else {
+            None
+        }
+    }
+}
+
+// Cell at (x + dx, y + dy), None past an edge that doesn't wrap
+fn wrapping_offset(boundary: BoundaryMode, width: usize, height: usize, x: usize, y: usize, dx: isize, dy: isize) -> Option<(usize, usize)> {
+    //a diagonal step out of a corner crosses two edges, both have to wrap
+    for (step_x, step_y) in [(dx, 0), (0, dy)] {
+        if let Some(edge) = BoundaryMode::crossed_edge(width, height, x, y, step_x, step_y) {
+            if boundary.edges[edge] != EdgeMode::Wrap {
+                return None;
+            }
+        }
+    }
+    let x = (x as isize + dx).rem_euclid(width as isize) as usize;
+    let y = (y as isize + dy).rem_euclid(height as isize) as usize;
+    Some((x, y))
+}
+
+// Whether going from (x, y) to (x + dx, y + dy) leaves the grid through an open edge
+fn leaves_grid(boundary: BoundaryMode, width: usize, height: usize, x: usize, y: usize, dx: isize, dy: isize) -> bool {
+    BoundaryMode::crossed_edge(width, height, x, y, dx, dy).is_some_and(|edge| boundary.edges[edge] == EdgeMode::Open)
+}
+
+// Cells of the 1 cell wide line from `a` to `b`, both included, in order
+pub fn line_cells(a: (isize, isize), b: (isize, isize)) -> Vec<(isize, isize)> {
+    //Bresenham, stepping along both axes at once on diagonals
+    let (dx, dy) = ((b.0 - a.0).abs(), -(b.1 - a.1).abs());
+    let (sx, sy) = ((b.0 - a.0).signum(), (b.1 - a.1).signum());
+    let (mut x, mut y) = a;
+    let mut error = dx + dy;
+    let mut cells = Vec::new();
+    loop {
+        cells.push((x, y));
+        if (x, y) == b {
+            return cells;
+        }
+        let error2 = 2 * error;
+        if error2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if error2 <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+// Read only view of the cells `plan_move` decides from
+#[derive(Clone, Copy)]
+pub struct CellView<'a> {
+    pub meta: &'a [u8],
+    pub velocity: &'a [Vec2],
+    pub settle: &'a [u8],
+    pub wetness: &'a [f32],
+    pub frozen: &'a [bool],
+    pub width: usize,
+    pub height: usize,
+    pub boundary: BoundaryMode,
+}
+
+impl CellView<'_> {
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    fn offset(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<(usize, usize)> {
+        wrapping_offset(self.boundary, self.width, self.height, x, y, dx, dy)
+    }
+
+    fn leaves_grid(&self, x: usize, y: usize, dx: isize, dy: isize) -> bool {
+        leaves_grid(self.boundary, self.width, self.height, x, y, dx, dy)
+    }
+
+    fn is_free(&self, x: usize, y: usize) -> bool {
+        let i = self.index(x, y);
+        !SandGrid::is_pixel_solid(self.meta[i]) && !self.frozen[i]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveParams {
+    pub dt: f32,
+    pub accel: Vec2,
+    pub travel: f32, //scales how far a cell moves for its velocity, see `SandGrid::substep_count`
+    pub max_velocity: f32,
+    pub repose_threshold: f32,
+    pub flows: bool, //spreads sideways when blocked and never holds a slope, like liquids and gases
+}
+
+// Outcome of `plan_move` for a single cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellMove {
+    pub velocity: Vec2, //velocity of the cell after the step
+    pub target: Option<(usize, usize)>, //cell it swaps with, None when it stays
+    pub falling: bool, //the target is along `accel`, not to the side
+    pub wake: bool, //it stays but may move in a later step
+    pub leaves: bool, //goes out through an open edge and is removed
+}
+
+// Decides where the cell at (x, y) goes: along the dominant axis of `accel`
+// until the first occupied cell, sliding diagonally when the cell right next
+// to it is taken, and when `params.flows` spreading sideways when blocked.
+// A cell launched against `accel` first goes against it until it runs out of
+// speed. Cells moving out through an open edge leave the grid. Only reads
+// `cells`, `rng` breaks ties. A cell at a solid edge of the grid along
+// `accel` rests on it, or spreads along it when it flows.
+pub fn plan_move(cells: &CellView, x: usize, y: usize, params: &MoveParams, rng: &mut impl Rng) -> CellMove {
+    let (dx, dy) = SandGrid::step_along(params.accel);
+    let i_current = cells.index(x, y);
+    let material = Material::from_id(cells.meta[i_current]);
+    let max_velocity = material.terminal_velocity().map_or(params.max_velocity, |v| v.min(params.max_velocity));
+    let v_next = (cells.velocity[i_current] + params.accel * params.dt).clamp_length_max(max_velocity);
+    let stay = |velocity, wake| CellMove { velocity, target: None, falling: false, wake, leaves: false };
+    let leave = |velocity| CellMove { velocity, target: None, falling: false, wake: false, leaves: true };
+
+    let dir = Vec2::new(dx as _, dy as _);
+    let along = v_next.dot(dir);
+    if along < 0.0 {
+        let distance = (-along * params.travel).round() as usize;
+        let (mut x_target, mut y_target) = (x, y);
+        let mut velocity = v_next;
+        for _ in 0..distance {
+            match cells.offset(x_target, y_target, -dx, -dy) {
+                Some((x_step, y_step)) if cells.is_free(x_step, y_step) => (x_target, y_target) = (x_step, y_step),
+                None if cells.leaves_grid(x_target, y_target, -dx, -dy) => return leave(velocity),
+                _ => {
+                    //hit a ceiling, only the speed along it is left
+                    velocity -= along * dir;
+                    break;
+                }
+            }
+        }
+        if (x_target, y_target) == (x, y) {
+            return stay(velocity, true);
+        }
+        return CellMove { velocity, target: Some((x_target, y_target)), falling: false, wake: false, leaves: false };
+    }
+
+    //liquids and gases spread along whatever blocks them, in a random direction to avoid drifting
+    let (side_x, side_y) = (dy.abs(), dx.abs());
+    let spread = |rng: &mut _| {
+        if !params.flows {
+            return None;
+        }
+        let first = if Rng::gen_bool(rng, 0.5) { 1 } else { -1 };
+        for s in [first, -first] {
+            match cells.offset(x, y, side_x * s, side_y * s) {
+                Some((x_side, y_side)) if cells.is_free(x_side, y_side) => {
+                    return Some(CellMove { velocity: v_next, target: Some((x_side, y_side)), falling: false, wake: false, leaves: false });
+                }
+                None if cells.leaves_grid(x, y, side_x * s, side_y * s) => return Some(leave(v_next)),
+                _ => {}
+            }
+        }
+        None
+    };
+
+    let Some((x_next, y_next)) = cells.offset(x, y, dx, dy) else {
+        if cells.leaves_grid(x, y, dx, dy) {
+            return leave(v_next);
+        }
+        //resting on a solid edge, but free to spread along it
+        return spread(rng).unwrap_or(stay(Vec2::ZERO, false));
+    };
+
+    //sinks through a lighter liquid or gas right under it, which rises in exchange
+    let i_next = cells.index(x_next, y_next);
+    let blocking = Material::from_id(cells.meta[i_next]);
+    if (blocking.is_liquid() || blocking.is_gas()) && blocking.density() < material.density() && !cells.frozen[i_next] {
+        return CellMove { velocity: v_next, target: Some((x_next, y_next)), falling: false, wake: false, leaves: false };
+    }
+
+    if v_next.length_squared() < 1.0 {
+        //still speeding up, worth another look if it has somewhere to go: the
+        //next cell, a slope or, when it flows, the cells next to it
+        let free = |cell: Option<(usize, usize)>| cell.is_some_and(|(x, y)| cells.is_free(x, y));
+        let slides = [-1, 1].into_iter().any(|s| free(cells.offset(x_next, y_next, side_x * s, side_y * s)));
+        let spreads = params.flows && [-1, 1].into_iter().any(|s| free(cells.offset(x, y, side_x * s, side_y * s)));
+        //with nowhere to go it rests, a sleeping chunk would keep its speed
+        let wake = cells.is_free(x_next, y_next) || slides || spreads;
+        return stay(if wake { v_next } else { Vec2::ZERO }, wake);
+    }
+
+    let distance = (v_next.dot(Vec2::new(dx as _, dy as _)).max(0.0) * params.travel).round() as usize;
+    let (mut x_target, mut y_target) = (x_next, y_next);
+    //find the next collision
+    let (mut x_scan, mut y_scan) = (x, y);
+    for _ in 0..distance {
+        let Some((x_step, y_step)) = cells.offset(x_scan, y_scan, dx, dy) else {
+            break;
+        };
+        (x_scan, y_scan) = (x_step, y_step);
+        if cells.offset(x_scan, y_scan, dx, dy).is_none() {
+            break;
+        }
+        if !cells.is_free(x_scan, y_scan) {
+            break;
+        }
+        (x_target, y_target) = (x_scan, y_scan);
+    }
+
+    if cells.is_free(x_target, y_target) {
+        return CellMove { velocity: v_next, target: Some((x_target, y_target)), falling: true, wake: false, leaves: false };
+    }
+
+    //settled grains don't look for a slope until something next to them moves
+    if cells.settle[i_current] >= SETTLE_TICKS {
+        return stay(Vec2::ZERO, false);
+    }
+
+    //TODO: remove the determinism of the sand always preferring to slide to the right first
+    let free_side = [(-side_x, -side_y), (side_x, side_y)].into_iter().find_map(|(sx, sy)| {
+        cells.offset(x_target, y_target, sx, sy).filter(|&(x_side, y_side)| cells.is_free(x_side, y_side))
+    });
+
+    //wet sand clumps, it slides less the more soaked it is
+    let repose_threshold = params.repose_threshold.max(material.repose());
+    let repose_threshold = repose_threshold + (1.0 - repose_threshold) * cells.wetness[i_current] * WET_REPOSE;
+    if !params.flows && repose_threshold > 0.0 && rng.gen::<f32>() < repose_threshold {
+        //held in place by chance, it may still slide in a later step
+        return stay(Vec2::ZERO, free_side.is_some());
+    }
+
+    if let Some(side) = free_side {
+        return CellMove { velocity: v_next, target: Some(side), falling: false, wake: false, leaves: false };
+    }
+
+    //the grain came to rest, once freed it has to accelerate from a standstill again
+    spread(rng).unwrap_or(stay(Vec2::ZERO, false))
+}
+
+impl SandGrid {
+    const DEFAULT_GRAVITY: Vec2 = Vec2::new(0.0, 9.81);
+    const DEFAULT_SEED: u64 = 0x5A4D;
+    const DEFAULT_MAX_VELOCITY: f32 = 20.0;
+    const DEFAULT_VELOCITY_COLORMAP: [(f32, [u8; 3]); 3] = [(0.0, [0, 0, 255]), (5.0, [0, 255, 0]), (10.0, [255, 0, 0])];
+    // Grains darken and warm up a little over the first minute of resting, as if compacting
+    const DEFAULT_AGING_COLORMAP: [(f32, [u8; 3]); 2] = [(0.0, [255, 255, 255]), (3600.0, [205, 195, 180])];
+    const DENSITY_COLORMAP: [(f32, [u8; 3]); 4] = [(0.0, [0, 0, 0]), (0.5, [120, 0, 160]), (0.8, [230, 90, 20]), (1.0, [255, 240, 160])];
+    // Painting and erasing cover the square of cells within this distance of the cursor
+    pub const BRUSH_RADIUS: usize = 10;
+    // Largest number of cells of a grid, about 32 bytes each across all the
+    // per cell buffers, that is half a GB at this size
+    pub const MAX_CELLS: usize = 4096 * 4096;
+    // Part of the width and height of the grid a stamped outline spans at most,
+    // leaving room around it for sand to pile up
+    const STAMP_FILL: f32 = 0.75;
+
+    // Grids are at least 1x1, smaller sizes are clamped to it. Fails past
+    // `MAX_CELLS` cells.
+    pub fn new(width: usize, height: usize) -> anyhow::Result<Self> {
+        let width = std::cmp::max(1, width);
+        let height = std::cmp::max(1, height);
+        match width.checked_mul(height) {
+            Some(cells) if cells <= Self::MAX_CELLS => {}
+            _ => anyhow::bail!("a {width}x{height} grid has more than the {} cells allowed", Self::MAX_CELLS),
+        }
+        let meta = vec![0; width * height];
+        let color = CpuTexture::new(
+            width as _,
+            height as _,
+            cpu_texture::new_texture_filled(width as _, height as _, Material::Empty.color()));
+
+        let velocity = vec![Vec2::ZERO; (width * height) as _];
+        let lifetime = vec![0; width * height];
+        let temperature = vec![AMBIENT_TEMPERATURE; width * height];
+        let shade = vec![0.0; width * height];
+        let trail = vec![(0, 0, 0, 0); width * height];
+        let settle = vec![0; width * height];
+        let age = vec![0; width * height];
+        let wetness = vec![0.0; width * height];
+        let chunk_columns = width.div_ceil(CHUNK_SIZE);
+        let chunk_count = chunk_columns * height.div_ceil(CHUNK_SIZE);
+
+        let mut grid = SandGrid {
+            width,
+            height,
+            meta,
+            color,
+            hdr: None,
+            velocity,
+            lifetime,
+            temperature,
+            shade,
+            color_jitter: 0.1,
+            trail,
+            settle,
+            age,
+            aging: false,
+            aging_colormap: Self::DEFAULT_AGING_COLORMAP.to_vec(),
+            wetness,
+            trails_enabled: false,
+            trails_fading: false,
+            wetness_changing: false,
+            gravity: Self::DEFAULT_GRAVITY,
+            max_velocity: Self::DEFAULT_MAX_VELOCITY,
+            boundary: BoundaryMode::SOLID,
+            heat_enabled: false,
+            emitters: Vec::new(),
+            attractors: Vec::new(),
+            wind: 0.0,
+            color_mode: ColorMode::Velocity,
+            velocity_colormap: Self::DEFAULT_VELOCITY_COLORMAP.to_vec(),
+            repose_threshold: 0.0,
+            max_cells_per_step: 4,
+            rng: StdRng::seed_from_u64(Self::DEFAULT_SEED),
+            dirty: None,
+            active_chunks: vec![false; chunk_count],
+            woken_chunks: vec![false; chunk_count],
+            aged_chunks: vec![false; chunk_count],
+            chunk_columns,
+            chunk_origin_x: 0,
+            settled_gravity: Self::DEFAULT_GRAVITY,
+            reactions: HashMap::new(),
+            behaviors: HashMap::new(),
+            moved_cells: 0,
+            drained_cells: 0,
+            drain: vec![false; width * height],
+            frozen: vec![false; width * height],
+            passes: vec![0; width * height],
+            grain_delta: 0,
+            report: None,
+        };
+        grid.add_reaction(Material::Water, Material::Fire, ReactionResult { a: Material::Steam, b: Material::Empty });
+        //lava poured into water hardens, the water boils off
+        grid.add_reaction(Material::Lava, Material::Water, ReactionResult { a: Material::Obsidian, b: Material::Steam });
+        Ok(grid)
+    }
+
+    // Makes a cell of `a` next to a cell of `b` turn into `result.a` and the
+    // other one into `result.b`, whichever of the two gets simulated first.
+    // Replaces any reaction between the same two materials.
+    pub fn add_reaction(&mut self, a: Material, b: Material, result: ReactionResult) {
+        self.reactions.remove(&(b, a));
+        self.reactions.insert((a, b), result);
+    }
+
+    // Makes cells of `material` move with `behavior` during the falling sweep
+    // instead of the built in behavior for it, see `behavior`. Cells that rise
+    // or were launched against gravity keep moving the built in way.
+    pub fn set_behavior(&mut self, material: Material, behavior: Arc<dyn ParticleBehavior>) {
+        self.behaviors.insert(material, behavior);
+    }
+
+    // Goes back to the built in behavior for `material`
+    pub fn reset_behavior(&mut self, material: Material) {
+        self.behaviors.remove(&material);
+    }
+
+    fn reaction(&self, a: Material, b: Material) -> Option<ReactionResult> {
+        if let Some(result) = self.reactions.get(&(a, b)) {
+            return Some(*result);
+        }
+        self.reactions.get(&(b, a)).map(|result| ReactionResult { a: result.b, b: result.a })
+    }
+
+    // Runs the first reaction between the cell at (x, y) and one of its 4
+    // neighbours, returns true when the cell changed
+    pub(crate) fn react(&mut self, x: usize, y: usize) -> bool {
+        let material = Material::from_id(self.meta[self.coord_to_index(x, y)]);
+        if material == Material::Acid {
+            return self.dissolve(x, y);
+        }
+        if self.reactions.is_empty() {
+            return false;
+        }
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let Some((nx, ny)) = self.offset(x, y, dx, dy) else {
+                continue;
+            };
+            if self.is_frozen(nx, ny) {
+                continue;
+            }
+            let neighbour = Material::from_id(self.meta[self.coord_to_index(nx, ny)]);
+            let Some(result) = self.reaction(material, neighbour) else {
+                continue;
+            };
+            self.grain_delta += Self::is_grain(result.a) as isize + Self::is_grain(result.b) as isize
+                - Self::is_grain(material) as isize - Self::is_grain(neighbour) as isize;
+            self.report_reaction(x, y, nx, ny, result);
+            self.set_cell(x, y, result.a);
+            self.set_cell(nx, ny, result.b);
+            return true;
+        }
+        false
+    }
+
+    // Lets the acid cell at (x, y) dissolve a dissolvable neighbour, by chance
+    // so walls are eaten through gradually. The acid is used up with it.
+    fn dissolve(&mut self, x: usize, y: usize) -> bool {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let Some((nx, ny)) = self.offset(x, y, dx, dy) else {
+                continue;
+            };
+            if !Material::from_id(self.meta[self.coord_to_index(nx, ny)]).is_dissolvable() || self.is_frozen(nx, ny) {
+                continue;
+            }
+            if !self.rng.gen_bool(ACID_DISSOLVE_CHANCE) {
+                //keeps its chunk awake to try again even once it stopped moving
+                self.wake(x, y);
+                return false;
+            }
+            self.grain_delta -= 2;
+            self.report_reaction(x, y, nx, ny, ReactionResult { a: Material::Empty, b: Material::Empty });
+            self.set_cell(x, y, Material::Empty);
+            self.set_cell(nx, ny, Material::Empty);
+            return true;
+        }
+        false
+    }
+
+    // Adds the reaction of the cells at (x, y) and (nx, ny), which haven't
+    // changed yet, to the report of the tick in progress
+    fn report_reaction(&mut self, x: usize, y: usize, nx: usize, ny: usize, result: ReactionResult) {
+        let a = Material::from_id(self.meta[self.coord_to_index(x, y)]);
+        let b = Material::from_id(self.meta[self.coord_to_index(nx, ny)]);
+        if let Some(report) = self.report.as_mut() {
+            report.reactions.push(Reaction { cell: (x, y), neighbour: (nx, ny), a, b, result });
+        }
+    }
+
+    // Restarts the random sequence used by spawning and the simulation, runs with
+    // the same seed and inputs give the same grid
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Takes over the simulation parameters of `other`, leaving the cells alone
+    pub fn copy_settings_from(&mut self, other: &SandGrid) {
+        self.gravity = other.gravity;
+        self.max_velocity = other.max_velocity;
+        self.boundary = other.boundary;
+        self.wind = other.wind;
+        self.heat_enabled = other.heat_enabled;
+        self.trails_enabled = other.trails_enabled;
+        self.color_jitter = other.color_jitter;
+        self.reactions = other.reactions.clone();
+        self.behaviors = other.behaviors.clone();
+        self.attractors = other.attractors.clone();
+        self.repose_threshold = other.repose_threshold;
+        self.color_mode = other.color_mode;
+        self.velocity_colormap = other.velocity_colormap.clone();
+        self.aging = other.aging;
+        self.aging_colormap = other.aging_colormap.clone();
+        self.max_cells_per_step = other.max_cells_per_step;
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    // Replaces the gradient of the velocity color mode, speeds between two stops
+    // blend their colors and speeds past the first or last stop take its color
+    pub fn set_velocity_colormap(&mut self, mut colormap: Vec<(f32, [u8; 3])>) {
+        colormap.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.velocity_colormap = colormap;
+    }
+
+    pub fn aging(&self) -> bool {
+        self.aging
+    }
+
+    // Starts or stops counting how long grains rest and shifting their color
+    // with it, see `set_aging_colormap`. Only the colors change, never the
+    // physics. Turning it off forgets the ages and repaints the grid.
+    pub fn set_aging(&mut self, enabled: bool) {
+        if self.aging == enabled {
+            return;
+        }
+        self.aging = enabled;
+        if !enabled {
+            self.age.fill(0);
+            self.aged_chunks.fill(false);
+            self.repaint();
+        }
+    }
+
+    // Replaces the colors resting grains are shifted by, the material color is
+    // multiplied by the color of their age, blended between the two closest
+    // stops like `set_velocity_colormap`
+    pub fn set_aging_colormap(&mut self, mut colormap: Vec<(f32, [u8; 3])>) {
+        colormap.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.aging_colormap = colormap;
+        //its end may have moved
+        self.aged_chunks.fill(false);
+    }
+
+    fn velocity_to_color(&self, v: f32) -> (u8, u8, u8) {
+        Self::colormap_color(&self.velocity_colormap, v)
+    }
+
+    // Color of `v` on a gradient of value and color pairs sorted by value
+    fn colormap_color(colormap: &[(f32, [u8; 3])], v: f32) -> (u8, u8, u8) {
+        let Some(&(first_speed, first_color)) = colormap.first() else {
+            return (255, 255, 255);
+        };
+        if v <= first_speed {
+            return (first_color[0], first_color[1], first_color[2]);
+        }
+        for pair in colormap.windows(2) {
+            let ((s0, c0), (s1, c1)) = (pair[0], pair[1]);
+            if v <= s1 {
+                let t = if s1 > s0 { (v - s0) / (s1 - s0) } else { 1.0 };
+                let blend = |i: usize| (c0[i] as f32 + (c1[i] as f32 - c0[i] as f32) * t).round() as u8;
+                return (blend(0), blend(1), blend(2));
+            }
+        }
+        let last_color = colormap[colormap.len() - 1].1;
+        (last_color[0], last_color[1], last_color[2])
+    }
+
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+        self.repaint();
+    }
+
+    // Keeps a linear half float copy of `color` up to date, with the cells of
+    // emissive materials brighter than 1.0 for a tonemapped HDR view. Off by
+    // default, the copy is only made while it is on.
+    pub fn set_hdr(&mut self, enabled: bool) {
+        if enabled == self.hdr.is_some() {
+            return;
+        }
+        self.hdr = enabled.then(|| HdrTexture::new(self.width, self.height));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.write_hdr_pixel(x, y);
+            }
+        }
+        //whatever shows the grid switches texture, it has to be uploaded again
+        self.dirty = Some(DirtyRect { x: 0, y: 0, width: self.width, height: self.height });
+    }
+
+    // The HDR colors, None unless `set_hdr` turned them on. Changes to them are
+    // part of the rectangle returned by `take_dirty`.
+    pub fn hdr_color(&self) -> Option<&HdrTexture> {
+        self.hdr.as_ref()
+    }
+
+    // Advances the grid by `dt` seconds like `simulate` and lists the cells that
+    // moved and the reactions that happened, for effects driven by what changed
+    pub fn tick(&mut self, dt: f32) -> TickReport {
+        self.report = Some(TickReport::default());
+        self.simulate(dt);
+        self.report.take().unwrap_or_default()
+    }
+
+    // Same as `tick` without building the report
+    pub fn simulate(&mut self, dt: f32) {
+        self.moved_cells = 0;
+        self.drained_cells = 0;
+        self.run_emitters();
+        let grains = cfg!(debug_assertions).then(|| self.grain_count());
+        let substeps = self.substep_count(dt);
+        for _ in 0..substeps {
+            if !self.begin_step() {
+                break;
+            }
+            self.simulate_columns(dt / substeps as f32, 0..self.width, 1.0 / substeps as f32);
+        }
+        self.check_grain_count(grains);
+        //burning changes the grain count, so it comes after the check
+        self.after_tick();
+    }
+
+    // Counts down every emitter and spawns the cells of the ones that are due,
+    // an emitter whose target cell is taken waits for it to be freed
+    fn run_emitters(&mut self) {
+        let (dx, dy) = Self::step_along(self.gravity);
+        for i in 0..self.emitters.len() {
+            let emitter = self.emitters[i];
+            if emitter.countdown > 0 {
+                self.emitters[i].countdown -= 1;
+                continue;
+            }
+            let Some((x, y)) = self.offset(emitter.x, emitter.y, dx, dy) else {
+                continue;
+            };
+            if Self::is_pixel_solid(self.meta[self.coord_to_index(x, y)]) {
+                continue;
+            }
+            self.set_cell(x, y, emitter.material);
+            self.emitters[i].countdown = emitter.interval.saturating_sub(1);
+        }
+    }
+
+    // Makes the chunks woken since the last step the active ones, returns false
+    // when there is nothing to simulate
+    fn begin_step(&mut self) -> bool {
+        //grains resting under the old gravity may fall under the new one
+        if self.gravity != self.settled_gravity {
+            self.settled_gravity = self.gravity;
+            self.woken_chunks.fill(true);
+            self.settle.fill(0);
+        }
+        //resting grains may be pulled away at any time
+        if !self.attractors.is_empty() {
+            self.woken_chunks.fill(true);
+        }
+        std::mem::swap(&mut self.active_chunks, &mut self.woken_chunks);
+        self.woken_chunks.fill(false);
+        self.active_chunks.contains(&true)
+    }
+
+    // Number of cells the next step will simulate, only cells in or next to
+    // chunks where something changed are. Drops to zero once everything settled.
+    pub fn active_cell_count(&self) -> usize {
+        self.woken_chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, woken)| **woken)
+            .map(|(chunk, _)| {
+                let x0 = chunk % self.chunk_columns * CHUNK_SIZE;
+                let y0 = chunk / self.chunk_columns * CHUNK_SIZE;
+                std::cmp::min(CHUNK_SIZE, self.width - x0) * std::cmp::min(CHUNK_SIZE, self.height - y0)
+            })
+            .sum()
+    }
+
+    // Number of sub steps needed for the fastest grain to travel at most
+    // `max_cells_per_step` cells per step. Each sub step advances time by
+    // dt / substeps and moves grains by velocity / substeps cells.
+    fn substep_count(&self, dt: f32) -> usize {
+        let fastest = self
+            .velocity
+            .iter()
+            .zip(&self.meta)
+            .filter(|(_, info)| Self::is_pixel_solid(**info))
+            .map(|(v, _)| v.length())
+            .fold(0.0, f32::max)
+            + self.gravity.length() * dt;
+        let fastest = fastest.min(self.max_velocity);
+        std::cmp::max(1, (fastest / self.max_cells_per_step as f32).ceil() as usize)
+    }
+
+    // The grid is split into 2 * (rayon thread count) vertical bands, each band
+    // is simulated by `simulate_columns` on its own copy of the cells. Even bands
+    // run in a first pass and odd bands in a second, and every band is at least
+    // 2 columns wide. A grain moves at most one column sideways, so the cells a
+    // band can write (its columns plus one halo column on each side) never overlap
+    // with the ones of another band in the same pass.
+    //
+    // Only vertical gravity keeps that sideways motion within one column, any
+    // other gravity, any attractor or wrapping edges fall back to `simulate`.
+    // Open left and right edges only apply to the bands along them.
+    //
+    // Ordering guarantees: within a band cells are visited in the same order as
+    // `simulate` (bottom row first, left to right), the even pass always completes
+    // before the odd pass, and bands of a pass are independent, so the result only
+    // depends on the band layout, i.e. on the thread count, never on scheduling.
+    // It does differ from `simulate` since grains can't cross a band boundary
+    // until the neighbour band has been processed. Each band gets its own random
+    // generator, seeded from the grid one in band order.
+    pub fn simulate_parallel(&mut self, dt: f32) {
+        if Self::step_along(self.gravity).0 != 0 || !self.attractors.is_empty() || self.boundary.wraps() {
+            self.simulate(dt);
+            return;
+        }
+
+        self.moved_cells = 0;
+        self.drained_cells = 0;
+        self.run_emitters();
+        let grains = cfg!(debug_assertions).then(|| self.grain_count());
+        let substeps = self.substep_count(dt);
+        for _ in 0..substeps {
+            if !self.begin_step() {
+                break;
+            }
+            self.simulate_parallel_step(dt / substeps as f32, 1.0 / substeps as f32);
+        }
+        self.check_grain_count(grains);
+        //burning changes the grain count, so it comes after the check
+        self.after_tick();
+    }
+
+    // Passes run once per tick by `simulate` and `simulate_parallel`, after the
+    // cells moved
+    fn after_tick(&mut self) {
+        self.update_heat();
+        self.update_wetness();
+        self.decay_trails();
+        self.update_density();
+        self.update_aging();
+    }
+
+    fn simulate_parallel_step(&mut self, dt: f32, travel: f32) {
+        let band_count = rayon::current_num_threads() * 2;
+        let band_width = std::cmp::max(2, self.width.div_ceil(band_count));
+
+        let bands: Vec<(usize, usize)> = (0..self.width)
+            .step_by(band_width)
+            .map(|x0| (x0, std::cmp::min(x0 + band_width, self.width)))
+            .collect();
+
+        for pass in 0..2 {
+            let mut work: Vec<(usize, std::ops::Range<usize>, SandGrid)> = bands
+                .iter()
+                .skip(pass)
+                .step_by(2)
+                .map(|&(x0, x1)| {
+                    let halo_x0 = x0.saturating_sub(1);
+                    let halo_x1 = std::cmp::min(x1 + 1, self.width);
+                    let columns = (x0 - halo_x0)..(x1 - halo_x0);
+                    (halo_x0, columns, self.copy_columns(halo_x0, halo_x1))
+                })
+                .collect();
+            for (_, _, band) in work.iter_mut() {
+                band.set_seed(self.rng.gen());
+            }
+
+            work.par_iter_mut().for_each(|(_, columns, band)| {
+                band.simulate_columns(dt, columns.clone(), travel);
+            });
+
+            for (x0, _, band) in &work {
+                self.paste_columns(band, *x0);
+            }
+        }
+    }
+
+    // Number of cells holding a material that never expires, moving cells around
+    // must keep it constant
+    pub fn grain_count(&self) -> usize {
+        self.meta
+            .iter()
+            .filter(|info| Self::is_grain(Material::from_id(**info)))
+            .count()
+    }
+
+    // FNV-1a hash of the size, the cells and their velocities quantized to 1/256th
+    // of a cell, stable across runs and platforms. Two runs from the same seed and
+    // inputs end with the same hash.
+    pub fn state_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut hash = FNV_OFFSET;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        write(&(self.width as u64).to_le_bytes());
+        write(&(self.height as u64).to_le_bytes());
+        write(&self.meta);
+        for v in &self.velocity {
+            write(&((v.x * 256.0).round() as i32).to_le_bytes());
+            write(&((v.y * 256.0).round() as i32).to_le_bytes());
+        }
+        hash
+    }
+
+    // Scans the whole grid, meant for debugging rather than every frame
+    pub fn stats(&self) -> SimStats {
+        let movable = self
+            .meta
+            .iter()
+            .filter(|info| {
+                let material = Material::from_id(**info);
+                material != Material::Empty && !material.is_static()
+            })
+            .count();
+        SimStats {
+            moved: self.moved_cells,
+            resting: movable.saturating_sub(self.moved_cells),
+            drained: self.drained_cells,
+        }
+    }
+
+    // Sum of 0.5 * m * v^2 over the cells that can move, with the density of
+    // their material as mass. A grid left alone should trend towards 0, energy
+    // that stays up points at jittering cells.
+    pub fn total_kinetic_energy(&self) -> f32 {
+        self.meta
+            .iter()
+            .zip(&self.velocity)
+            .zip(&self.frozen)
+            .filter(|&(_, frozen)| !*frozen)
+            .map(|((info, velocity), _)| {
+                let material = Material::from_id(*info);
+                if material == Material::Empty || material.is_static() {
+                    return 0.0;
+                }
+                0.5 * material.density() * velocity.length_squared()
+            })
+            .sum()
+    }
+
+    fn is_grain(material: Material) -> bool {
+        material != Material::Empty && material.lifetime() == 0
+    }
+
+    // Catches grains created or destroyed by a simulation step, other than by
+    // reactions, only counted in debug builds
+    fn check_grain_count(&mut self, grains_before: Option<usize>) {
+        let grain_delta = std::mem::take(&mut self.grain_delta);
+        if let Some(grains_before) = grains_before {
+            let expected = grains_before.checked_add_signed(grain_delta).expect("reactions destroyed more grains than there were");
+            assert_eq!(expected, self.grain_count(), "simulation step created or destroyed grains");
+        }
+    }
+
+    // `travel` scales how far a grain moves for its velocity, see `substep_count`
+    fn simulate_columns(&mut self, dt: f32, columns: std::ops::Range<usize>, travel: f32) {
+        let gravity = self.gravity;
+
+        //falling cells, furthest along gravity first so a grain only moves once per tick
+        self.for_each_cell_along(gravity, columns.clone(), |grid, x, y| {
+            let material = Material::from_id(grid.meta[grid.coord_to_index(x, y)]);
+            if material == Material::Empty || material.rises() || grid.is_launched(x, y) || grid.is_frozen(x, y) {
+                return;
+            }
+            let custom = grid.behaviors.get(&material).cloned();
+            let mut ctx = CellContext::new(grid, x, y, dt, travel);
+            match custom {
+                Some(custom) => custom.update(&mut ctx),
+                None => behavior::builtin(material).update(&mut ctx),
+            }
+        });
+
+        //liquid surfaces, once everything fell, so connected liquids settle flat
+        self.for_each_cell_along(gravity, columns.clone(), |grid, x, y| {
+            if Material::from_id(grid.meta[grid.coord_to_index(x, y)]).is_liquid() && !grid.is_frozen(x, y) {
+                grid.level_liquid(x, y);
+            }
+        });
+
+        //rising cells and cells launched against gravity, furthest against gravity first
+        self.for_each_cell_along(-gravity, columns, |grid, x, y| {
+            let material = Material::from_id(grid.meta[grid.coord_to_index(x, y)]);
+            if grid.is_frozen(x, y) {
+                return;
+            }
+            if material.rises() {
+                if !grid.react(x, y) && grid.age_cell(x, y) {
+                    grid.move_cell(x, y, dt, -gravity, travel, material.is_liquid() || material.is_gas());
+                }
+            } else if grid.is_launched(x, y) && !grid.react(x, y) {
+                let force = grid.force_at(x, y);
+                grid.move_cell(x, y, dt, force, travel, material.is_liquid() || material.is_gas());
+            }
+        });
+    }
+
+    // Whether the cell at (x, y) is still moving against gravity, such cells are
+    // moved in the rising sweep so they too only move once per tick
+    fn is_launched(&self, x: usize, y: usize) -> bool {
+        let i = self.coord_to_index(x, y);
+        let material = Material::from_id(self.meta[i]);
+        material != Material::Empty && !material.is_static() && !material.rises() && self.velocity[i].dot(self.gravity) < 0.0
+    }
+
+    // Acceleration of a falling cell at (x, y), gravity plus the pull of every attractor
+    pub(crate) fn force_at(&self, x: usize, y: usize) -> Vec2 {
+        let position = Vec2::new(x as f32, y as f32);
+        self.attractors.iter().fold(self.gravity, |force, attractor| {
+            let to_attractor = Vec2::new(attractor.x, attractor.y) - position;
+            let distance = to_attractor.length();
+            if distance < 1.0 {
+                return force;
+            }
+            force + to_attractor / distance * (attractor.strength / distance)
+        })
+    }
+
+    // Visits the cells of `columns` in active chunks, starting with the ones
+    // furthest along `dir`
+    fn for_each_cell_along(&mut self, dir: Vec2, columns: std::ops::Range<usize>, mut f: impl FnMut(&mut Self, usize, usize)) {
+        let (dx, dy) = Self::step_along(dir);
+        if dy != 0 {
+            let rows: Vec<usize> = if dy > 0 { (0..self.height).rev().collect() } else { (0..self.height).collect() };
+            for y in rows {
+                for x in columns.clone() {
+                    if self.active_chunks[self.chunk_index(x, y)] {
+                        f(self, x, y);
+                    }
+                }
+            }
+        } else {
+            let columns: Vec<usize> = if dx > 0 { columns.rev().collect() } else { columns.collect() };
+            for x in columns {
+                for y in 0..self.height {
+                    if self.active_chunks[self.chunk_index(x, y)] {
+                        f(self, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    fn chunk_index(&self, x: usize, y: usize) -> usize {
+        y / CHUNK_SIZE * self.chunk_columns + (self.chunk_origin_x + x) / CHUNK_SIZE
+    }
+
+    // Schedules the chunks around (x, y) for the next step
+    fn wake(&mut self, x: usize, y: usize) {
+        let chunk_rows = self.woken_chunks.len() / self.chunk_columns;
+        let x = self.chunk_origin_x + x;
+        for chunk_y in y.saturating_sub(1) / CHUNK_SIZE..=(y + 1) / CHUNK_SIZE {
+            for chunk_x in x.saturating_sub(1) / CHUNK_SIZE..=(x + 1) / CHUNK_SIZE {
+                if chunk_x < self.chunk_columns && chunk_y < chunk_rows {
+                    self.woken_chunks[chunk_y * self.chunk_columns + chunk_x] = true;
+                }
+            }
+        }
+    }
+
+    // Unit step along the dominant axis of `dir`
+    fn step_along(dir: Vec2) -> (isize, isize) {
+        if dir.y.abs() >= dir.x.abs() {
+            (0, if dir.y < 0.0 { -1 } else { 1 })
+        } else {
+            (if dir.x < 0.0 { -1 } else { 1 }, 0)
+        }
+    }
+
+    // Cell at (x + dx, y + dy), None past a solid edge
+    pub(crate) fn offset(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<(usize, usize)> {
+        wrapping_offset(self.boundary, self.width, self.height, x, y, dx, dy)
+    }
+
+    // Moves the cell at (x, y) as decided by `plan_move`
+    pub(crate) fn move_cell(&mut self, x: usize, y: usize, dt: f32, accel: Vec2, travel: f32, flows: bool) {
+        let cells = CellView { meta: &self.meta, velocity: &self.velocity, settle: &self.settle, wetness: &self.wetness, frozen: &self.frozen, width: self.width, height: self.height, boundary: self.boundary };
+        let params = MoveParams { dt, accel, travel, max_velocity: self.max_velocity, repose_threshold: self.repose_threshold, flows };
+        let plan = plan_move(&cells, x, y, &params, &mut self.rng);
+        if plan.leaves {
+            self.remove_cell(x, y);
+            return;
+        }
+
+        let i_current = self.coord_to_index(x, y);
+        self.velocity[i_current] = plan.velocity;
+        self.tint_cell(x, y);
+        if plan.wake {
+            self.wake(x, y);
+        }
+        let Some((x_target, y_target)) = plan.target else {
+            self.update_settle(x, y, accel);
+            return;
+        };
+
+        self.moved_cells += 1;
+        self.swap_cell(x, y, x_target, y_target);
+        //nothing left to trail or drift when it was drained
+        if plan.falling && Self::is_pixel_solid(self.meta[self.coord_to_index(x_target, y_target)]) {
+            let (dx, dy) = Self::step_along(accel);
+            let cells = x.abs_diff(x_target) + y.abs_diff(y_target);
+            if self.trails_enabled && cells > 1 {
+                let (r, g, b, _) = self.color.get_pixel(x_target, y_target);
+                self.leave_trail(x, y, dx, dy, cells, (r, g, b));
+            }
+            self.drift(x_target, y_target, dx, dy);
+        }
+    }
+
+    // Counts the ticks the grain at (x, y) spent on a base filled right below it
+    // and on both sides of that, starting over when the base isn't full. Past
+    // `SETTLE_TICKS` the grain is settled and stops jittering between the
+    // diagonals of a slope. Liquids and gases never settle.
+    fn update_settle(&mut self, x: usize, y: usize, accel: Vec2) {
+        let i = self.coord_to_index(x, y);
+        let material = Material::from_id(self.meta[i]);
+        if material.is_liquid() || material.is_gas() {
+            return;
+        }
+        let (dx, dy) = Self::step_along(accel);
+        let (side_x, side_y) = (dy.abs(), dx.abs());
+        let is_solid = |cell: Option<(usize, usize)>| cell.is_none_or(|(x, y)| Self::is_pixel_solid(self.meta[self.coord_to_index(x, y)]));
+        let supported = match self.offset(x, y, dx, dy) {
+            Some((x_below, y_below)) => {
+                is_solid(Some((x_below, y_below)))
+                    && is_solid(self.offset(x_below, y_below, -side_x, -side_y))
+                    && is_solid(self.offset(x_below, y_below, side_x, side_y))
+            }
+            None => true, //resting on a solid edge
+        };
+        self.settle[i] = if supported { self.settle[i].saturating_add(1) } else { 0 };
+    }
+
+    // Disturbs the grains around (x, y), they look for a slope again and may
+    // get wetter or drier. The cell at (x, y) changed, its chunk ages again.
+    fn unsettle(&mut self, x: usize, y: usize) {
+        self.wetness_changing = true;
+        let chunk = self.chunk_index(x, y);
+        self.aged_chunks[chunk] = false;
+        for ny in y.saturating_sub(1)..=std::cmp::min(y + 1, self.height - 1) {
+            for nx in x.saturating_sub(1)..=std::cmp::min(x + 1, self.width - 1) {
+                let i = self.coord_to_index(nx, ny);
+                self.settle[i] = 0;
+            }
+        }
+    }
+
+    // Moves the liquid cell at (x, y), when it is at the surface, onto the
+    // surface of the same liquid in a neighbour column at least 2 cells lower.
+    // Columns run along gravity, repeating this levels connected liquids out
+    // where spreading alone leaves them piled up.
+    fn level_liquid(&mut self, x: usize, y: usize) {
+        let (dx, dy) = Self::step_along(self.gravity);
+        let Some((x_above, y_above)) = self.offset(x, y, -dx, -dy) else {
+            return;
+        };
+        if Self::is_pixel_solid(self.meta[self.coord_to_index(x_above, y_above)]) {
+            return;
+        }
+
+        let material = self.meta[self.coord_to_index(x, y)];
+        let column_length = if dy != 0 { self.height } else { self.width };
+        let (side_x, side_y) = (dy.abs(), dx.abs());
+        let first = if self.rng.gen_bool(0.5) { 1 } else { -1 };
+        for s in [first, -first] {
+            let Some((mut x_target, mut y_target)) = self.offset(x, y, side_x * s, side_y * s) else {
+                continue;
+            };
+            if Self::is_pixel_solid(self.meta[self.coord_to_index(x_target, y_target)]) {
+                continue;
+            }
+            //height difference between the two surfaces, in cells
+            let mut drop = 1;
+            let mut below = self.offset(x_target, y_target, dx, dy);
+            while let Some((x_below, y_below)) = below {
+                //a wrapping empty column never ends
+                if drop >= column_length || Self::is_pixel_solid(self.meta[self.coord_to_index(x_below, y_below)]) {
+                    break;
+                }
+                (x_target, y_target) = (x_below, y_below);
+                drop += 1;
+                below = self.offset(x_target, y_target, dx, dy);
+            }
+            let on_same_liquid = below.is_some_and(|(x_below, y_below)| self.meta[self.coord_to_index(x_below, y_below)] == material);
+            if drop >= 2 && on_same_liquid && !self.frozen[self.coord_to_index(x_target, y_target)] {
+                self.moved_cells += 1;
+                self.swap_cell(x, y, x_target, y_target);
+                return;
+            }
+        }
+    }
+
+    // Pushes a grain that just moved along (dx, dy) one cell sideways with a
+    // chance of wind / density, the expected displacement grows with the wind
+    // and lighter materials drift further
+    fn drift(&mut self, x: usize, y: usize, dx: isize, dy: isize) {
+        if self.wind == 0.0 {
+            return;
+        }
+        let material = Material::from_id(self.meta[self.coord_to_index(x, y)]);
+        let chance = (self.wind.abs() / material.density()).min(1.0);
+        if !self.rng.gen_bool(chance as f64) {
+            return;
+        }
+
+        let s = if self.wind > 0.0 { 1 } else { -1 };
+        if let Some((x_side, y_side)) = self.offset(x, y, dy.abs() * s, dx.abs() * s) {
+            let i_side = self.coord_to_index(x_side, y_side);
+            if !Self::is_pixel_solid(self.meta[i_side]) && !self.frozen[i_side] {
+                self.swap_cell(x, y, x_side, y_side);
+            }
+        }
+    }
+
+    // Tints the `cells` empty cells a grain passed through, starting at (x, y)
+    // and going along (dx, dy)
+    fn leave_trail(&mut self, x: usize, y: usize, dx: isize, dy: isize, cells: usize, (r, g, b): (u8, u8, u8)) {
+        let (mut x, mut y) = (x, y);
+        for _ in 0..cells {
+            let i = self.coord_to_index(x, y);
+            if !Self::is_pixel_solid(self.meta[i]) {
+                self.trail[i] = (r, g, b, TRAIL_ALPHA);
+                self.set_color(x, y, r, g, b, TRAIL_ALPHA);
+                self.trails_fading = true;
+            }
+            let Some(next) = self.offset(x, y, dx, dy) else {
+                break;
+            };
+            (x, y) = next;
+        }
+    }
+
+    // Fades every trail a bit, empty cells show what is left of theirs.
+    // Keeps running after trails are disabled until the last one faded out.
+    fn decay_trails(&mut self) {
+        if !self.trails_fading {
+            return;
+        }
+        self.trails_fading = false;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = self.coord_to_index(x, y);
+                if self.trail[i].3 == 0 {
+                    continue;
+                }
+                self.trail[i].3 = self.trail[i].3.saturating_sub(TRAIL_DECAY);
+                self.trails_fading |= self.trail[i].3 > 0;
+                if !Self::is_pixel_solid(self.meta[i]) {
+                    let (r, g, b, a) = self.empty_color(i);
+                    self.set_color(x, y, r, g, b, a);
+                }
+            }
+        }
+    }
+
+    // Color of the empty cell `i`, its trail if it has one
+    fn empty_color(&self, i: usize) -> (u8, u8, u8, u8) {
+        match self.trail[i] {
+            (_, _, _, 0) => Material::Empty.color(),
+            trail => trail,
+        }
+    }
+
+    // Every cell takes the average temperature of itself and its 4 neighbours,
+    // then materials with a fixed temperature reset their cells to it and
+    // flammable cells past their ignition temperature catch fire and cells past
+    // their melting temperature melt
+    fn update_heat(&mut self) {
+        if !self.heat_enabled {
+            return;
+        }
+
+        let mut diffused = vec![AMBIENT_TEMPERATURE; self.temperature.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut sum = self.temperature[self.coord_to_index(x, y)];
+                let mut count = 1.0;
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    if let Some((nx, ny)) = self.offset(x, y, dx, dy) {
+                        sum += self.temperature[self.coord_to_index(nx, ny)];
+                        count += 1.0;
+                    }
+                }
+                diffused[self.coord_to_index(x, y)] = sum / count;
+            }
+        }
+        self.temperature = diffused;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = self.coord_to_index(x, y);
+                let material = Material::from_id(self.meta[i]);
+                if let Some(temperature) = material.temperature() {
+                    self.temperature[i] = temperature;
+                }
+                if material.ignition_temperature().is_some_and(|ignition| self.temperature[i] > ignition) {
+                    self.set_cell(x, y, Material::Fire);
+                } else if let Some((_, melted)) = material.melting().filter(|(melting, _)| self.temperature[i] > *melting) {
+                    self.set_cell(x, y, melted);
+                }
+            }
+        }
+
+        if self.color_mode == ColorMode::Temperature {
+            self.repaint();
+        }
+    }
+
+    // Sand next to water soaks some of it up every tick, other sand slowly dries
+    // out. Other materials stay dry. Once every grain is soaked or dry and no
+    // cell changed, nothing is left to do until one does. In the wetness color
+    // mode only the cells whose color changed are redrawn.
+    fn update_wetness(&mut self) {
+        if !self.wetness_changing {
+            return;
+        }
+        self.wetness_changing = false;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = self.coord_to_index(x, y);
+                if Material::from_id(self.meta[i]) == Material::Sand {
+                    let near_water = [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().any(|(dx, dy)| {
+                        self.offset(x, y, dx, dy).is_some_and(|(nx, ny)| Material::from_id(self.meta[self.coord_to_index(nx, ny)]) == Material::Water)
+                    });
+                    let wetness = if near_water {
+                        (self.wetness[i] + WETTING_RATE).min(1.0)
+                    } else {
+                        (self.wetness[i] - DRYING_RATE).max(0.0)
+                    };
+                    self.wetness_changing |= wetness != self.wetness[i];
+                    self.wetness[i] = wetness;
+                }
+                //cells set since the last pass were drawn with their material color
+                if self.color_mode == ColorMode::Wetness {
+                    let (r, g, b, a) = Self::wetness_color(self.wetness[i]);
+                    self.set_color(x, y, r, g, b, a);
+                }
+            }
+        }
+    }
+
+    // Density depends on the neighbours of a cell, every cell is recolored once
+    // the step is over
+    fn update_density(&mut self) {
+        if self.color_mode == ColorMode::Density {
+            self.repaint();
+        }
+    }
+
+    // Ages every settled grain by one tick, cells reset theirs when they move,
+    // and in the material color mode recolors the ones whose new age changes
+    // their color. Liquids and gases never settle, so they never age. Chunks
+    // whose grains all reached the end of `aging_colormap` are skipped until
+    // one of their cells changes.
+    fn update_aging(&mut self) {
+        if !self.aging {
+            return;
+        }
+        let oldest = self.aging_colormap.last().map_or(0.0, |&(age, _)| age);
+        for chunk in 0..self.aged_chunks.len() {
+            if self.aged_chunks[chunk] {
+                continue;
+            }
+            let x0 = chunk % self.chunk_columns * CHUNK_SIZE;
+            let y0 = chunk / self.chunk_columns * CHUNK_SIZE;
+            let mut aged = true;
+            for y in y0..std::cmp::min(y0 + CHUNK_SIZE, self.height) {
+                for x in x0..std::cmp::min(x0 + CHUNK_SIZE, self.width) {
+                    let i = self.coord_to_index(x, y);
+                    let material = Material::from_id(self.meta[i]);
+                    if !Self::is_grain(material) || material.is_liquid() || material.is_gas() || self.age[i] as f32 >= oldest {
+                        continue;
+                    }
+                    aged = false;
+                    //grains resting on a full base are settled, static ones always are
+                    if !material.is_static() && self.settle[i] == 0 {
+                        continue;
+                    }
+                    let tint = Self::colormap_color(&self.aging_colormap, self.age[i] as f32);
+                    self.age[i] = self.age[i].saturating_add(1);
+                    if self.color_mode == ColorMode::Material && Self::colormap_color(&self.aging_colormap, self.age[i] as f32) != tint {
+                        let (r, g, b, a) = self.cell_color(i);
+                        self.set_color(x, y, r, g, b, a);
+                    }
+                }
+            }
+            self.aged_chunks[chunk] = aged;
+        }
+    }
+
+    // Fraction of the cells in the square DENSITY_RADIUS cells around each cell
+    // holding something that piles up, from a summed area table of them. Cells
+    // of the square outside of the grid aren't counted.
+    fn packing_density(&self) -> Vec<f32> {
+        let filled = |i: usize| {
+            let material = Material::from_id(self.meta[i]);
+            material != Material::Empty && !material.rises()
+        };
+        //sums[(y + 1) * (width + 1) + x + 1] counts the filled cells above and left of (x, y), inclusive
+        let stride = self.width + 1;
+        let mut sums = vec![0u32; stride * (self.height + 1)];
+        for y in 0..self.height {
+            let mut row = 0;
+            for x in 0..self.width {
+                row += filled(self.coord_to_index(x, y)) as u32;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            }
+        }
+
+        let mut density = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            let (y0, y1) = (y.saturating_sub(DENSITY_RADIUS), std::cmp::min(y + DENSITY_RADIUS + 1, self.height));
+            for x in 0..self.width {
+                let (x0, x1) = (x.saturating_sub(DENSITY_RADIUS), std::cmp::min(x + DENSITY_RADIUS + 1, self.width));
+                let count = sums[y1 * stride + x1] + sums[y0 * stride + x0] - sums[y0 * stride + x1] - sums[y1 * stride + x0];
+                density.push(count as f32 / ((x1 - x0) * (y1 - y0)) as f32);
+            }
+        }
+        density
+    }
+
+    // Number of cells between the bottom row and the top of the highest cell
+    // that piles up in column x, fire and gases aren't counted. 0 for an empty
+    // column or outside of the grid.
+    pub fn column_height(&self, x: usize) -> usize {
+        if x >= self.width {
+            return 0;
+        }
+        (0..self.height)
+            .find(|&y| {
+                let material = Material::from_id(self.meta[self.coord_to_index(x, y)]);
+                material != Material::Empty && !material.rises()
+            })
+            .map_or(0, |y| self.height - y)
+    }
+
+    // Number of cells moved into (x, y) since the grid was created, wrapping
+    // around. Sampled twice it gives the flow through that cell. None outside
+    // of the grid.
+    pub fn pass_count(&self, x: usize, y: usize) -> Option<u32> {
+        if x < self.width && y < self.height {
+            Some(self.passes[self.coord_to_index(x, y)])
+        } else {
+            None
+        }
+    }
+
+    // Material of the cell at (x, y), None outside of the grid
+    pub fn material_at(&self, x: usize, y: usize) -> Option<Material> {
+        if x < self.width && y < self.height {
+            Some(Material::from_id(self.meta[self.coord_to_index(x, y)]))
+        } else {
+            None
+        }
+    }
+
+    // Temperature of the cell at (x, y), None outside of the grid
+    pub fn heat_at(&self, x: usize, y: usize) -> Option<f32> {
+        if x < self.width && y < self.height {
+            Some(self.temperature[self.coord_to_index(x, y)])
+        } else {
+            None
+        }
+    }
+
+    // Wetness of the cell at (x, y) from 0 to 1, None outside of the grid
+    pub fn wetness_at(&self, x: usize, y: usize) -> Option<f32> {
+        if x < self.width && y < self.height {
+            Some(self.wetness[self.coord_to_index(x, y)])
+        } else {
+            None
+        }
+    }
+
+    fn wetness_color(wetness: f32) -> (u8, u8, u8, u8) {
+        let b = (wetness.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (0, b / 2, b, 255)
+    }
+
+    fn temperature_color(temperature: f32) -> (u8, u8, u8, u8) {
+        if temperature < AMBIENT_TEMPERATURE {
+            let cold = (AMBIENT_TEMPERATURE - temperature) / AMBIENT_TEMPERATURE;
+            let b = (cold.min(1.0) * 255.0).round() as u8;
+            (0, 0, b, 255)
+        } else {
+            let hot = (temperature - AMBIENT_TEMPERATURE) / 800.0;
+            let r = ((hot * 3.0).min(1.0) * 255.0).round() as u8;
+            let g = ((hot * 3.0 - 1.0).clamp(0.0, 1.0) * 255.0).round() as u8;
+            (r, g, 0, 255)
+        }
+    }
+
+    // Counts down the lifetime of the cell, returns false once it expired
+    fn age_cell(&mut self, x: usize, y: usize) -> bool {
+        let i = self.coord_to_index(x, y);
+        let material = Material::from_id(self.meta[i]);
+        if material.lifetime() == 0 {
+            return true;
+        }
+
+        self.wake(x, y);
+        self.lifetime[i] = self.lifetime[i].saturating_sub(1);
+        if self.lifetime[i] > 0 {
+            return true;
+        }
+
+        let expired = material.expires_into();
+        self.meta[i] = expired.id();
+        self.velocity[i] = Vec2::ZERO;
+        self.lifetime[i] = expired.lifetime();
+        let (r, g, b, a) = self.cell_color(i);
+        self.set_color(x, y, r, g, b, a);
+        self.unsettle(x, y);
+        false
+    }
+
+    fn tint_cell(&mut self, x: usize, y: usize) {
+        let i = self.coord_to_index(x, y);
+        match Material::from_id(self.meta[i]) {
+            Material::Fire => {
+                //yellow when lit, red when about to burn out
+                let age = self.lifetime[i] as f32 / Material::Fire.lifetime() as f32;
+                self.set_color(x, y, 255, (age * 255.0).round() as u8, 0, 255);
+            }
+            Material::Smoke => {
+                //fades out as it gets older, the sand quad is alpha blended
+                let age = self.lifetime[i] as f32 / Material::Smoke.lifetime() as f32;
+                let (r, g, b, a) = Material::Smoke.color();
+                self.set_color(x, y, r, g, b, (age * a as f32).round() as u8);
+            }
+            _ if self.color_mode == ColorMode::Velocity => {
+                let (r, g, b) = self.velocity_to_color(self.velocity[i].length());
+                self.set_color(x, y, r, g, b, 255);
+            }
+            //keeps the material color it was painted with
+            _ => {}
+        }
+    }
+
+    fn coord_to_index(&self, x: usize, y: usize) -> usize {
+        y*self.width + x
+    }
+
+    fn copy_columns(&self, x0: usize, x1: usize) -> SandGrid {
+        let width = x1 - x0;
+        let mut band = SandGrid::new(width, self.height).expect("a band is smaller than its grid");
+        band.copy_settings_from(self);
+        //the sides of a band inside the grid hold cells like the halo columns around them
+        if x0 > 0 {
+            band.boundary.edges[BoundaryMode::LEFT] = EdgeMode::Solid;
+        }
+        if x1 < self.width {
+            band.boundary.edges[BoundaryMode::RIGHT] = EdgeMode::Solid;
+        }
+        for y in 0..self.height {
+            let src = self.coord_to_index(x0, y);
+            let dst = band.coord_to_index(0, y);
+            band.meta[dst..dst + width].copy_from_slice(&self.meta[src..src + width]);
+            band.velocity[dst..dst + width].copy_from_slice(&self.velocity[src..src + width]);
+            band.lifetime[dst..dst + width].copy_from_slice(&self.lifetime[src..src + width]);
+            band.temperature[dst..dst + width].copy_from_slice(&self.temperature[src..src + width]);
+            band.shade[dst..dst + width].copy_from_slice(&self.shade[src..src + width]);
+            band.trail[dst..dst + width].copy_from_slice(&self.trail[src..src + width]);
+            band.settle[dst..dst + width].copy_from_slice(&self.settle[src..src + width]);
+            band.age[dst..dst + width].copy_from_slice(&self.age[src..src + width]);
+            band.wetness[dst..dst + width].copy_from_slice(&self.wetness[src..src + width]);
+            band.drain[dst..dst + width].copy_from_slice(&self.drain[src..src + width]);
+            band.frozen[dst..dst + width].copy_from_slice(&self.frozen[src..src + width]);
+            band.passes[dst..dst + width].copy_from_slice(&self.passes[src..src + width]);
+            for x in 0..width {
+                let (r, g, b, a) = self.color.get_pixel(x0 + x, y);
+                band.color.set_pixel(x, y, r, g, b, a);
+            }
+        }
+        band.active_chunks = self.active_chunks.clone();
+        band.woken_chunks = vec![false; self.woken_chunks.len()];
+        band.aged_chunks = self.aged_chunks.clone();
+        band.chunk_columns = self.chunk_columns;
+        band.chunk_origin_x = self.chunk_origin_x + x0;
+        band
+    }
+
+    fn paste_columns(&mut self, band: &SandGrid, x0: usize) {
+        for y in 0..band.height {
+            let src = band.coord_to_index(0, y);
+            let dst = self.coord_to_index(x0, y);
+            self.meta[dst..dst + band.width].copy_from_slice(&band.meta[src..src + band.width]);
+            self.velocity[dst..dst + band.width].copy_from_slice(&band.velocity[src..src + band.width]);
+            self.lifetime[dst..dst + band.width].copy_from_slice(&band.lifetime[src..src + band.width]);
+            self.temperature[dst..dst + band.width].copy_from_slice(&band.temperature[src..src + band.width]);
+            self.shade[dst..dst + band.width].copy_from_slice(&band.shade[src..src + band.width]);
+            self.trail[dst..dst + band.width].copy_from_slice(&band.trail[src..src + band.width]);
+            self.settle[dst..dst + band.width].copy_from_slice(&band.settle[src..src + band.width]);
+            self.age[dst..dst + band.width].copy_from_slice(&band.age[src..src + band.width]);
+            self.wetness[dst..dst + band.width].copy_from_slice(&band.wetness[src..src + band.width]);
+            self.passes[dst..dst + band.width].copy_from_slice(&band.passes[src..src + band.width]);
+            for x in 0..band.width {
+                let (r, g, b, a) = band.color.get_pixel(x, y);
+                self.color.set_pixel(x0 + x, y, r, g, b, a);
+            }
+        }
+        if let Some(rect) = band.dirty {
+            self.mark_dirty(x0 + rect.x, rect.y);
+            self.mark_dirty(x0 + rect.x + rect.width - 1, rect.y + rect.height - 1);
+            //bands don't keep HDR colors, they are redone from what changed
+            if self.hdr.is_some() {
+                for y in rect.y..rect.y + rect.height {
+                    for x in x0 + rect.x..x0 + rect.x + rect.width {
+                        self.write_hdr_pixel(x, y);
+                    }
+                }
+            }
+        }
+        for (woken, band_woken) in self.woken_chunks.iter_mut().zip(&band.woken_chunks) {
+            *woken |= *band_woken;
+        }
+        for (aged, band_aged) in self.aged_chunks.iter_mut().zip(&band.aged_chunks) {
+            *aged &= *band_aged;
+        }
+        self.grain_delta += band.grain_delta;
+        self.moved_cells += band.moved_cells;
+        self.drained_cells += band.drained_cells;
+        self.trails_fading |= band.trails_fading;
+        self.wetness_changing |= band.wetness_changing;
+    }
+
+        
+    pub fn spawn_sand_at(&mut self,x: usize, y: usize) -> bool {
+        self.spawn_material_at(x, y, Material::Sand)
+    }
+
+    // Paints the brush around (x, y), the part of it outside of the grid is
+    // clipped. Returns false and does nothing when (x, y) itself is outside.
+    pub fn spawn_material_at(&mut self, x: usize, y: usize, material: Material) -> bool {
+        self.spawn_brush(x, y, material, None, BrushShape::DEFAULT)
+    }
+
+    // Same as `spawn_material_at` with a brush of `shape`
+    pub fn spawn_shape_at(&mut self, x: usize, y: usize, material: Material, shape: BrushShape) -> bool {
+        self.spawn_brush(x, y, material, None, shape)
+    }
+
+    // Same as `spawn_material_at` with the painted cells starting at velocity `v`
+    // in cells per second, cells launched against gravity fly until it stops
+    // them. Static materials don't move and keep a zero velocity.
+    pub fn spawn_with_velocity(&mut self, x: usize, y: usize, material: Material, v: Vec2) -> bool {
+        self.spawn_brush(x, y, material, Some(v), BrushShape::DEFAULT)
+    }
+
+    fn spawn_brush(&mut self, x: usize, y: usize, material: Material, velocity: Option<Vec2>, shape: BrushShape) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        for (dx, dy) in shape.offsets() {
+            let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                continue;
+            };
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            //static materials are painted solid so walls don't leak
+            if !material.is_static() && self.rng.gen_bool(0.5) {
+                continue;
+            }
+            self.set_cell(x, y, material);
+            if let Some(v) = velocity.filter(|_| material != Material::Empty && !material.is_static()) {
+                let i = self.coord_to_index(x, y);
+                self.velocity[i] = v.clamp_length_max(self.max_velocity);
+            }
+        }
+        true
+    }
+
+    // Fills the 4-connected region of cells sharing the material found at (x, y)
+    pub fn flood_fill(&mut self, x: usize, y: usize, material: Material) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let target = self.meta[self.coord_to_index(x, y)];
+        if target == material.id() {
+            return;
+        }
+
+        //explicit queue rather than recursion, regions can span the whole grid
+        let mut queue = std::collections::VecDeque::new();
+        self.set_cell(x, y, material);
+        queue.push_back((x, y));
+        while let Some((x, y)) = queue.pop_front() {
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let Some((nx, ny)) = self.offset(x, y, dx, dy) else {
+                    continue;
+                };
+                if self.meta[self.coord_to_index(nx, ny)] == target {
+                    self.set_cell(nx, ny, material);
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+    }
+
+    // Replaces the content of a cell with a fresh cell of `material`
+    fn set_cell(&mut self, x: usize, y: usize, material: Material) {
+        let i = self.coord_to_index(x, y);
+
+        self.meta[i] = material.id();
+        self.velocity[i] = match material {
+            Material::Empty => Vec2::ZERO,
+            _ if material.is_static() => Vec2::ZERO,
+            _ if material.rises() => Vec2::new(0.0, -1.0),
+            _ => Vec2::new(0.0, 1.0),
+        };
+        self.lifetime[i] = material.lifetime();
+        if let Some(temperature) = material.temperature() {
+            self.temperature[i] = temperature;
+        }
+        if material != Material::Empty {
+            self.shade[i] = self.rng.gen_range(-1.0..=1.0);
+        }
+        self.wetness[i] = 0.0;
+        self.age[i] = 0;
+        let (r, g, b, a) = self.cell_color(i);
+        self.set_color(x, y, r, g, b, a);
+        self.wake(x, y);
+        self.unsettle(x, y);
+    }
+
+    // Places a single cell of `material`, outside of the grid it does nothing
+    pub fn set_material_at(&mut self, x: usize, y: usize, material: Material) {
+        if x < self.width && y < self.height {
+            self.set_cell(x, y, material);
+        }
+    }
+
+    // Fills the rectangle with `material`, clipped to the grid. Unlike painting
+    // every cell is set, so scenes built from it are the same on every run.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, material: Material) {
+        for y in y..std::cmp::min(y.saturating_add(height), self.height) {
+            for x in x..std::cmp::min(x.saturating_add(width), self.width) {
+                self.set_cell(x, y, material);
+            }
+        }
+    }
+
+    // Empties every cell, like `fill_rect` over the whole grid
+    pub fn clear(&mut self) {
+        self.fill_rect(0, 0, self.width, self.height, Material::Empty);
+    }
+
+    // Sets the cells of the 1 cell wide line from `a` to `b`, both included.
+    // The ends may lie outside of the grid, only the part inside is drawn.
+    pub fn draw_line(&mut self, a: (isize, isize), b: (isize, isize), material: Material) {
+        for (x, y) in line_cells(a, b) {
+            if x >= 0 && y >= 0 {
+                self.set_material_at(x as usize, y as usize, material);
+            }
+        }
+    }
+
+    // Fills the cells covered by `triangles`, the outline of a model seen from
+    // the front with y up, with `material`. The outline is scaled to fit the
+    // middle `STAMP_FILL` of the grid, keeping its proportions, and centered.
+    // A cell is covered when its center lies inside a triangle.
+    pub fn stamp_model_outline(&mut self, triangles: &[[Vec2; 3]], material: Material) {
+        let corners = triangles.iter().flatten();
+        let min = corners.clone().fold(Vec2::INFINITY, |min, &corner| min.min(corner));
+        let max = corners.fold(Vec2::NEG_INFINITY, |max, &corner| max.max(corner));
+        let size = max - min;
+        if !size.is_finite() || size.x <= 0.0 || size.y <= 0.0 {
+            return;
+        }
+        let grid_size = Vec2::new(self.width as f32, self.height as f32);
+        let scale = (grid_size * Self::STAMP_FILL / size).min_element();
+        let center = (min + max) / 2.0;
+        let to_grid = |point: Vec2| {
+            let offset = (point - center) * scale;
+            grid_size / 2.0 + Vec2::new(offset.x, -offset.y)
+        };
+
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(to_grid);
+            //signed areas, the same sign for all three edges inside whatever the winding
+            let edge = |from: Vec2, to: Vec2, point: Vec2| (to - from).perp_dot(point - from);
+            if edge(a, b, c) == 0.0 {
+                continue;
+            }
+            let low = a.min(b).min(c).max(Vec2::ZERO);
+            let high = a.max(b).max(c).min(grid_size);
+            for y in low.y as usize..high.y.ceil() as usize {
+                for x in low.x as usize..high.x.ceil() as usize {
+                    let point = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let sides = [edge(a, b, point), edge(b, c, point), edge(c, a, point)];
+                    if sides.iter().all(|&side| side >= 0.0) || sides.iter().all(|&side| side <= 0.0) {
+                        self.set_cell(x, y, material);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stacks `height` cells of `material` at the bottom of column `x`, clipped
+    // to the grid
+    pub fn spawn_column(&mut self, x: usize, height: usize, material: Material) {
+        let height = std::cmp::min(height, self.height);
+        self.fill_rect(x, self.height - height, 1, height, material);
+    }
+
+    // Same brush and bounds as `spawn_material_at`
+    pub fn erase_at(&mut self, x: usize, y: usize) -> bool {
+        self.erase_shape_at(x, y, BrushShape::DEFAULT)
+    }
+
+    // Same brush and bounds as `spawn_shape_at`
+    pub fn erase_shape_at(&mut self, x: usize, y: usize, shape: BrushShape) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        for (dx, dy) in shape.offsets() {
+            if let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) {
+                self.set_material_at(x, y, Material::Empty);
+            }
+        }
+        true
+    }
+
+    // Copies the cells of the rectangle, clipped to the grid
+    pub fn copy_region(&self, x: usize, y: usize, width: usize, height: usize) -> GridRegion {
+        let x = std::cmp::min(x, self.width);
+        let y = std::cmp::min(y, self.height);
+        let width = std::cmp::min(width, self.width - x);
+        let height = std::cmp::min(height, self.height - y);
+        let mut region = GridRegion {
+            x,
+            y,
+            width,
+            height,
+            meta: Vec::with_capacity(width * height),
+            velocity: Vec::with_capacity(width * height),
+            lifetime: Vec::with_capacity(width * height),
+            temperature: Vec::with_capacity(width * height),
+            shade: Vec::with_capacity(width * height),
+            wetness: Vec::with_capacity(width * height),
+        };
+        for row in y..y + height {
+            let range = self.coord_to_index(x, row)..self.coord_to_index(x + width, row);
+            region.meta.extend_from_slice(&self.meta[range.clone()]);
+            region.velocity.extend_from_slice(&self.velocity[range.clone()]);
+            region.lifetime.extend_from_slice(&self.lifetime[range.clone()]);
+            region.temperature.extend_from_slice(&self.temperature[range.clone()]);
+            region.shade.extend_from_slice(&self.shade[range.clone()]);
+            region.wetness.extend_from_slice(&self.wetness[range]);
+        }
+        region
+    }
+
+    // Puts back cells copied by `copy_region`, the region must come from a grid
+    // of the same size
+    pub fn paste_region(&mut self, region: &GridRegion) {
+        for row in 0..region.height {
+            let src = row * region.width..(row + 1) * region.width;
+            let dst = self.coord_to_index(region.x, region.y + row)..self.coord_to_index(region.x + region.width, region.y + row);
+            self.meta[dst.clone()].copy_from_slice(&region.meta[src.clone()]);
+            self.velocity[dst.clone()].copy_from_slice(&region.velocity[src.clone()]);
+            self.lifetime[dst.clone()].copy_from_slice(&region.lifetime[src.clone()]);
+            self.temperature[dst.clone()].copy_from_slice(&region.temperature[src.clone()]);
+            self.shade[dst.clone()].copy_from_slice(&region.shade[src.clone()]);
+            self.age[dst.clone()].fill(0);
+            self.wetness[dst].copy_from_slice(&region.wetness[src]);
+            for x in region.x..region.x + region.width {
+                let (r, g, b, a) = self.cell_color(self.coord_to_index(x, region.y + row));
+                self.set_color(x, region.y + row, r, g, b, a);
+                self.wake(x, region.y + row);
+                self.unsettle(x, region.y + row);
+            }
+        }
+    }
+
+    fn is_pixel_solid(info:u8) -> bool {
+        info!=0
+    }
+
+    // Material color of the cell `i`, brightened or darkened by its shade and
+    // shifted by its age while aging is on
+    fn cell_color(&self, i: usize) -> (u8, u8, u8, u8) {
+        let material = Material::from_id(self.meta[i]);
+        let (r, g, b, a) = material.color();
+        if material == Material::Empty || (self.color_jitter == 0.0 && !self.aging) {
+            return (r, g, b, a);
+        }
+        let brightness = 1.0 + self.shade[i] * self.color_jitter;
+        let tint = if self.aging { Self::colormap_color(&self.aging_colormap, self.age[i] as f32) } else { (255, 255, 255) };
+        let shift = |c: u8, t: u8| (c as f32 * brightness * t as f32 / 255.0).round().clamp(0.0, 255.0) as u8;
+        (shift(r, tint.0), shift(g, tint.1), shift(b, tint.2), a)
+    }
+
+    // Repaints every cell from its meta data, e.g. after a load
+    fn repaint(&mut self) {
+        let density = if self.color_mode == ColorMode::Density { self.packing_density() } else { Vec::new() };
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = self.coord_to_index(x, y);
+                let (r, g, b, a) = match self.color_mode {
+                    ColorMode::Temperature => Self::temperature_color(self.temperature[i]),
+                    ColorMode::Wetness => Self::wetness_color(self.wetness[i]),
+                    ColorMode::Density => {
+                        let (r, g, b) = Self::colormap_color(&Self::DENSITY_COLORMAP, density[i]);
+                        (r, g, b, 255)
+                    }
+                    _ => self.cell_color(i),
+                };
+                self.color.set_pixel(x, y, r, g, b, a);
+                self.write_hdr_pixel(x, y);
+            }
+        }
+        self.dirty = Some(DirtyRect { x: 0, y: 0, width: self.width, height: self.height });
+    }
+
+    // File layout: magic, width and height as u32, one meta byte per cell and
+    // then the velocity of every cell as two f32, everything little endian.
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(SAVE_FILE_MAGIC)?;
+        writer.write_all(&(self.width as u32).to_le_bytes())?;
+        writer.write_all(&(self.height as u32).to_le_bytes())?;
+        writer.write_all(&self.meta)?;
+        for v in &self.velocity {
+            writer.write_all(&v.x.to_le_bytes())?;
+            writer.write_all(&v.y.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<SandGrid> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SAVE_FILE_MAGIC {
+            anyhow::bail!("{path} is not a sand grid file");
+        }
+
+        let mut word = [0u8; 4];
+        reader.read_exact(&mut word)?;
+        let width = u32::from_le_bytes(word) as usize;
+        reader.read_exact(&mut word)?;
+        let height = u32::from_le_bytes(word) as usize;
+        if width == 0 || height == 0 {
+            anyhow::bail!("{path} has invalid grid dimensions {width}x{height}");
+        }
+
+        let mut grid = SandGrid::new(width, height).with_context(|| format!("{path} holds a grid too large to load"))?;
+        reader.read_exact(&mut grid.meta)?;
+        for v in grid.velocity.iter_mut() {
+            reader.read_exact(&mut word)?;
+            let x = f32::from_le_bytes(word);
+            reader.read_exact(&mut word)?;
+            let y = f32::from_le_bytes(word);
+            *v = Vec2::new(x, y);
+        }
+        for (lifetime, info) in grid.lifetime.iter_mut().zip(&grid.meta) {
+            *lifetime = Material::from_id(*info).lifetime();
+        }
+        //shades aren't saved, loaded cells get fresh ones
+        for shade in grid.shade.iter_mut() {
+            *shade = grid.rng.gen_range(-1.0..=1.0);
+        }
+        grid.woken_chunks.fill(true);
+        grid.repaint();
+        Ok(grid)
+    }
+
+    // Builds a grid the size of the PNG at `path`, every pixel becomes a cell of
+    // the material `color_to_material` maps its color to, None leaves it empty
+    pub fn from_image(path: &str, color_to_material: impl Fn((u8, u8, u8, u8)) -> Option<Material>) -> anyhow::Result<SandGrid> {
+        let (width, height, pixels) = cpu_texture::decode_png(path)?;
+        let image = CpuTexture::new(width as _, height as _, pixels);
+
+        let mut grid = SandGrid::new(width as _, height as _)?;
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                if let Some(material) = color_to_material(image.get_pixel(x, y)) {
+                    grid.set_cell(x, y, material);
+                }
+            }
+        }
+        Ok(grid)
+    }
+
+    // Swaps the cell at (x, y) with the one at (x1, y1) as a move of the step,
+    // for behaviors moving cells themselves
+    pub(crate) fn move_cell_to(&mut self, x: usize, y: usize, x1: usize, y1: usize) {
+        self.moved_cells += 1;
+        self.swap_cell(x, y, x1, y1);
+    }
+
+    fn swap_cell(&mut self, x: usize, y: usize, x1: usize, y1: usize) {
+        let i = y*self.width + x;
+        let i1 = y1*self.width + x1;
+
+        //swap sand info data
+        self.meta.swap(i, i1);
+
+        //swap color data
+        let pixel = self.color.get_pixel(x, y);
+        let pixel1 = self.color.get_pixel(x1, y1);
+        let (r,g,b,a) = pixel;
+        self.set_color(x1, y1, r, g, b, a);
+        let (r,g,b,a) = pixel1;
+        self.set_color(x, y, r, g, b, a);
+
+        if let Some(report) = self.report.as_mut() {
+            for (from, to, i_to) in [((x, y), (x1, y1), i1), ((x1, y1), (x, y), i)] {
+                if Self::is_pixel_solid(self.meta[i_to]) {
+                    report.moved.push(MovedCell { from, to, material: Material::from_id(self.meta[i_to]) });
+                }
+            }
+        }
+
+        //swap velocity data
+        self.velocity.swap(i, i1);
+        self.lifetime.swap(i, i1);
+        self.temperature.swap(i, i1);
+        self.shade.swap(i, i1);
+        self.wetness.swap(i, i1);
+        //moving starts the age over
+        self.age[i] = 0;
+        self.age[i1] = 0;
+
+        for i in [i, i1] {
+            if Self::is_pixel_solid(self.meta[i]) {
+                self.passes[i] = self.passes[i].wrapping_add(1);
+            }
+        }
+
+        //a trail stays where it was left instead of following the empty cell
+        for (x, y, i) in [(x, y, i), (x1, y1, i1)] {
+            if self.trails_fading && !Self::is_pixel_solid(self.meta[i]) {
+                let (r, g, b, a) = self.empty_color(i);
+                self.set_color(x, y, r, g, b, a);
+            }
+        }
+
+        self.wake(x, y);
+        self.wake(x1, y1);
+        self.unsettle(x, y);
+        self.unsettle(x1, y1);
+
+        self.drain_cell(x, y);
+        self.drain_cell(x1, y1);
+    }
+
+    // Makes (x, y) remove any cell moving into it, or stop doing so. Cells
+    // already there stay until they move. Outside of the grid it does nothing.
+    pub fn set_drain(&mut self, x: usize, y: usize, drain: bool) {
+        if x < self.width && y < self.height {
+            let i = self.coord_to_index(x, y);
+            self.drain[i] = drain;
+        }
+    }
+
+    pub fn is_drain(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.drain[self.coord_to_index(x, y)]
+    }
+
+    // Locks the cells of the rectangle, clipped to the grid, in place or unlocks
+    // them. Frozen cells don't move, react or age and nothing moves into them,
+    // painting still changes them.
+    pub fn set_frozen(&mut self, x: usize, y: usize, width: usize, height: usize, frozen: bool) {
+        for y in y..std::cmp::min(y.saturating_add(height), self.height) {
+            for x in x..std::cmp::min(x.saturating_add(width), self.width) {
+                let i = self.coord_to_index(x, y);
+                self.frozen[i] = frozen;
+                //thawed cells may have to move again
+                self.wake(x, y);
+            }
+        }
+    }
+
+    pub fn is_frozen(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.frozen[self.coord_to_index(x, y)]
+    }
+
+    // Removes the cell at (x, y) when it sits on a drain
+    fn drain_cell(&mut self, x: usize, y: usize) {
+        let i = self.coord_to_index(x, y);
+        if self.drain[i] && Self::is_pixel_solid(self.meta[i]) {
+            self.remove_cell(x, y);
+        }
+    }
+
+    // Takes the cell at (x, y) out of the grid, counted as drained
+    fn remove_cell(&mut self, x: usize, y: usize) {
+        self.grain_delta -= Self::is_grain(Material::from_id(self.meta[self.coord_to_index(x, y)])) as isize;
+        self.drained_cells += 1;
+        self.set_cell(x, y, Material::Empty);
+    }
+
+    fn set_color(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8, a: u8) {
+        if self.color.get_pixel(x, y) == (r, g, b, a) {
+            return;
+        }
+        self.color.set_pixel(x, y, r, g, b, a);
+        self.write_hdr_pixel(x, y);
+        self.mark_dirty(x, y);
+    }
+
+    // Updates the HDR color of the cell from its `color`, scaled by the emission
+    // of its material. Does nothing while HDR is off.
+    fn write_hdr_pixel(&mut self, x: usize, y: usize) {
+        if self.hdr.is_none() {
+            return;
+        }
+        let (r, g, b, a) = self.color.get_pixel(x, y);
+        let emission = Material::from_id(self.meta[self.coord_to_index(x, y)]).emission();
+        //the color bytes are sRGB, blending and tonemapping work on linear values
+        let linear = |c: u8| (c as f32 / 255.0).powf(2.2) * emission;
+        let rgba = [linear(r), linear(g), linear(b), a as f32 / 255.0];
+        if let Some(hdr) = self.hdr.as_mut() {
+            hdr.set_pixel(x, y, rgba);
+        }
+    }
+
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        match self.dirty.as_mut() {
+            Some(rect) => rect.include(x, y),
+            None => self.dirty = Some(DirtyRect::from_point(x, y)),
+        }
+    }
+
+    // Marks every cell as changed, for when whatever shows the grid was
+    // showing another one
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = Some(DirtyRect { x: 0, y: 0, width: self.width, height: self.height });
+    }
+
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn grid(width: usize, height: usize) -> SandGrid {
+        SandGrid::new(width, height).unwrap()
+    }
+
+    fn run(grid: &mut SandGrid, ticks: usize) {
+        for _ in 0..ticks {
+            grid.simulate(DT);
+        }
+    }
+
+    // Checks the grain count after every tick, not only at the end, so a grain
+    // lost in one tick and made up for in another still fails
+    fn assert_conserved(grid: &mut SandGrid, ticks: usize) {
+        let grains = grid.grain_count();
+        for tick in 0..ticks {
+            grid.simulate(DT);
+            assert_eq!(grains, grid.grain_count(), "grain count changed on tick {tick}");
+        }
+    }
+
+    #[test]
+    fn open_grid_keeps_its_grains() {
+        let mut grid = grid(64, 64);
+        grid.fill_rect(8, 0, 48, 16, Material::Sand);
+        assert_eq!(48 * 16, grid.grain_count());
+        assert_conserved(&mut grid, 300);
+    }
+
+    #[test]
+    fn sand_falling_against_a_wall_keeps_its_grains() {
+        let mut grid = grid(64, 64);
+        grid.fill_rect(40, 8, 2, 56, Material::Stone);
+        //one pile right against the wall, one against the edge of the grid
+        grid.fill_rect(28, 0, 12, 24, Material::Sand);
+        grid.fill_rect(52, 0, 12, 24, Material::Sand);
+        assert_conserved(&mut grid, 300);
+    }
+
+    #[test]
+    fn sand_piling_on_a_floor_keeps_its_grains() {
+        let mut grid = grid(64, 64);
+        grid.fill_rect(0, 48, 64, 2, Material::Stone);
+        grid.fill_rect(24, 0, 16, 32, Material::Sand);
+        assert_conserved(&mut grid, 300);
+        //nothing slipped through the floor
+        assert!((50..64).all(|y| (0..64).all(|x| grid.material_at(x, y) == Some(Material::Empty))));
+    }
+
+    #[test]
+    fn buried_grain_stays_put_and_falls_from_rest_once_freed() {
+        let mut grid = grid(32, 32);
+        let floor = 16;
+        grid.fill_rect(0, floor, 32, 1, Material::Stone);
+        grid.fill_rect(8, floor - 8, 17, 8, Material::Sand);
+        let (x, y) = (16, floor - 1);
+        run(&mut grid, 120);
+        assert_eq!(Some(Material::Sand), grid.material_at(x, y));
+        assert_eq!(Vec2::ZERO, grid.velocity[grid.coord_to_index(x, y)]);
+
+        //a hole in the floor under it, the grains above speed up from a
+        //standstill instead of shooting through it
+        grid.set_material_at(x, floor, Material::Empty);
+        grid.simulate(DT);
+        assert!((floor + 1..32).all(|y| grid.material_at(x, y) == Some(Material::Empty)), "a grain jumped through the hole");
+
+        run(&mut grid, 60);
+        assert_eq!(Some(Material::Sand), grid.material_at(x, 31), "no grain fell through the hole");
+    }
+
+    #[test]
+    fn settled_pile_has_no_active_cells() {
+        let mut grid = grid(64, 64);
+        grid.fill_rect(24, 0, 16, 24, Material::Sand);
+        assert!(grid.active_cell_count() > 0);
+        let mut ticks = 0;
+        while grid.active_cell_count() > 0 {
+            assert!(ticks < 1000, "the pile never settled, {} cells still active", grid.active_cell_count());
+            grid.simulate(DT);
+            ticks += 1;
+        }
+        //and it stays settled
+        run(&mut grid, 10);
+        assert_eq!(0, grid.active_cell_count());
+    }
+
+    #[test]
+    fn blocked_water_keeps_its_chunk_awake_until_it_spread() {
+        let mut grid = grid(64, 16);
+        grid.fill_rect(0, 8, 4, 8, Material::Water);
+        run(&mut grid, 600);
+        //a puddle at most 1 cell deep, not a slope left behind by a sleeping chunk
+        assert!((0..64).all(|x| grid.column_height(x) <= 1), "{:?}", (0..64).map(|x| grid.column_height(x)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn water_levels_out_in_a_stone_basin() {
+        let mut grid = grid(48, 32);
+        //a U of stone, with water poured against its left arm
+        grid.fill_rect(4, 31, 40, 1, Material::Stone);
+        grid.fill_rect(4, 8, 2, 23, Material::Stone);
+        grid.fill_rect(42, 8, 2, 23, Material::Stone);
+        grid.fill_rect(6, 8, 6, 23, Material::Water);
+        assert_conserved(&mut grid, 600);
+
+        let water = |grid: &SandGrid, x: usize| (0..32).filter(|&y| grid.material_at(x, y) == Some(Material::Water)).count();
+        let (left, right) = (grid.column_height(6), grid.column_height(41));
+        assert!(left.abs_diff(right) <= 1, "left arm at {left} cells, right arm at {right}");
+        assert_eq!(6 * 23, (6..42).map(|x| water(&grid, x)).sum::<usize>(), "water leaked out of the basin");
+    }
+
+    #[test]
+    fn fast_grain_stops_on_a_single_stone_row() {
+        let mut grid = grid(8, 256);
+        grid.max_velocity = 200.0;
+        grid.fill_rect(0, 128, 8, 1, Material::Stone);
+        grid.set_material_at(4, 100, Material::Sand);
+        let i = grid.coord_to_index(4, 100);
+        grid.velocity[i] = Vec2::new(0.0, 200.0);
+
+        //200 cells a tick, the row is 27 cells away
+        run(&mut grid, 60);
+        assert_eq!(Some(Material::Sand), grid.material_at(4, 127));
+        assert!((129..256).all(|y| (0..8).all(|x| grid.material_at(x, y) == Some(Material::Empty))), "the grain went through the stone row");
+    }
+
+    #[test]
+    fn fire_meeting_water_turns_into_steam() {
+        let steam = ReactionResult { a: Material::Steam, b: Material::Empty };
+        //whichever of the two cells reacts, the water becomes the steam
+        for (x, y) in [(3, 7), (4, 7)] {
+            let mut grid = grid(8, 8);
+            grid.add_reaction(Material::Water, Material::Fire, steam);
+            grid.set_material_at(3, 7, Material::Water);
+            grid.set_material_at(4, 7, Material::Fire);
+            assert!(grid.react(x, y));
+            assert_eq!(Some(Material::Steam), grid.material_at(3, 7));
+            assert_eq!(Some(Material::Empty), grid.material_at(4, 7));
+        }
+    }
+
+    #[test]
+    fn reactions_happen_while_simulating() {
+        let mut grid = grid(8, 8);
+        grid.add_reaction(Material::Water, Material::Fire, ReactionResult { a: Material::Steam, b: Material::Empty });
+        grid.set_material_at(3, 7, Material::Water);
+        grid.set_material_at(4, 7, Material::Fire);
+        let report = grid.tick(DT);
+        assert_eq!(1, report.reactions.len());
+        assert_eq!((Material::Water, Material::Fire), (report.reactions[0].a, report.reactions[0].b));
+        assert!((0..8).any(|y| (0..8).any(|x| grid.material_at(x, y) == Some(Material::Steam))));
+        assert!((0..8).all(|y| (0..8).all(|x| !matches!(grid.material_at(x, y), Some(Material::Water | Material::Fire)))));
+    }
+
+    // Sand and water poured on a stone shelf, with the random choices of the
+    // simulation driven by `seed`
+    fn seeded_scene(seed: u64) -> SandGrid {
+        let mut grid = grid(32, 32);
+        grid.set_seed(seed);
+        grid.draw_line((4, 20), (28, 24), Material::Stone);
+        grid.fill_rect(6, 0, 8, 8, Material::Sand);
+        grid.fill_rect(18, 2, 8, 8, Material::Water);
+        grid.repose_threshold = 0.3;
+        grid
+    }
+
+    #[test]
+    fn seeded_runs_end_with_the_same_hash() {
+        let hashes: Vec<u64> = (0..2)
+            .map(|_| {
+                let mut grid = seeded_scene(7);
+                run(&mut grid, 1000);
+                grid.state_hash()
+            })
+            .collect();
+        assert_eq!(hashes[0], hashes[1]);
+
+        //and the hash does tell grids apart
+        let mut other = seeded_scene(8);
+        run(&mut other, 1000);
+        assert_ne!(hashes[0], other.state_hash());
+    }
+
+    const SAND_FALLING: MoveParams = MoveParams { dt: DT, accel: Vec2::new(0.0, 9.81), travel: 1.0, max_velocity: 20.0, repose_threshold: 0.0, flows: false };
+
+    // Plans the move of the grain in the middle of a 3x3 neighbourhood given
+    // row by row from the top, `#` for sand and `.` for empty cells, falling at
+    // `speed` cells a tick
+    fn plan_middle(rows: [&str; 3], speed: f32) -> CellMove {
+        let meta: Vec<u8> = rows.concat().chars().map(|c| if c == '#' { Material::Sand.id() } else { Material::Empty.id() }).collect();
+        let mut velocity = vec![Vec2::ZERO; 9];
+        velocity[4] = Vec2::new(0.0, speed);
+        let cells = CellView {
+            meta: &meta,
+            velocity: &velocity,
+            settle: &[0; 9],
+            wetness: &[0.0; 9],
+            frozen: &[false; 9],
+            width: 3,
+            height: 3,
+            boundary: BoundaryMode::SOLID,
+        };
+        plan_move(&cells, 1, 1, &SAND_FALLING, &mut StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn plan_move_falls_into_the_free_cell_below() {
+        let plan = plan_middle(["...", ".#.", "..."], 2.0);
+        assert_eq!(Some((1, 2)), plan.target);
+        assert!(plan.falling);
+    }
+
+    #[test]
+    fn plan_move_slides_down_the_free_diagonal() {
+        let plan = plan_middle(["...", ".#.", ".##"], 2.0);
+        assert_eq!(Some((0, 2)), plan.target);
+        assert!(!plan.falling);
+    }
+
+    #[test]
+    fn plan_move_rests_on_a_full_base() {
+        let plan = plan_middle(["...", ".#.", "###"], 2.0);
+        assert_eq!(None, plan.target);
+        assert_eq!(Vec2::ZERO, plan.velocity, "a grain coming to rest loses its speed");
+    }
+
+    #[test]
+    fn plan_move_waits_while_speeding_up() {
+        //too slow to move a cell yet, but with somewhere to go it asks to be simulated again
+        let plan = plan_middle(["...", ".#.", "..."], 0.0);
+        assert_eq!(None, plan.target);
+        assert!(plan.wake);
+        assert!(plan.velocity.y > 0.0);
+    }
+
+    #[test]
+    fn plan_move_rests_on_the_bottom_edge() {
+        let meta = [Material::Sand.id()];
+        let cells = CellView { meta: &meta, velocity: &[Vec2::new(0.0, 5.0)], settle: &[0], wetness: &[0.0], frozen: &[false], width: 1, height: 1, boundary: BoundaryMode::SOLID };
+        let plan = plan_move(&cells, 0, 0, &SAND_FALLING, &mut StdRng::seed_from_u64(0));
+        assert_eq!(None, plan.target);
+        assert_eq!(Vec2::ZERO, plan.velocity);
+        assert!(!plan.leaves);
+    }
+
+    #[test]
+    fn falling_grain_levels_off_at_max_velocity() {
+        let mut grid = grid(1, 512);
+        grid.max_velocity = 5.0;
+        grid.set_material_at(0, 0, Material::Sand);
+        let mut speeds = Vec::new();
+        for _ in 0..60 {
+            grid.simulate(DT);
+            let i = grid.meta.iter().position(|&info| info == Material::Sand.id()).unwrap();
+            assert!(i < 511, "the grain reached the bottom");
+            speeds.push(grid.velocity[i].length());
+        }
+        assert!(speeds.iter().all(|&speed| speed <= 5.0));
+        assert!(speeds[10] < 5.0, "sped up too fast");
+        //reaches it after 5 / (9.81 / 60) ~ 31 ticks and stays there
+        assert!(speeds[35..].iter().all(|&speed| speed == 5.0), "{speeds:?}");
+    }
+
+    #[test]
+    fn wrapping_grid_keeps_its_grains() {
+        //down, and sideways as an endless conveyor
+        for gravity in [Vec2::new(0.0, 9.81), Vec2::new(9.81, 0.0)] {
+            let mut grid = grid(32, 32);
+            grid.boundary = BoundaryMode::WRAP;
+            grid.gravity = gravity;
+            //half a floor, what misses it goes around the grid
+            grid.fill_rect(0, 24, 16, 2, Material::Stone);
+            grid.fill_rect(4, 4, 20, 12, Material::Sand);
+            grid.fill_rect(20, 18, 8, 4, Material::Water);
+            assert_conserved(&mut grid, 300);
+        }
+    }
+
+    #[test]
+    fn oil_floats_up_through_water() {
+        let mut grid = grid(16, 32);
+        //oil at the bottom of the basin, water poured over it
+        grid.fill_rect(0, 24, 16, 8, Material::Oil);
+        grid.fill_rect(0, 16, 16, 8, Material::Water);
+        assert_conserved(&mut grid, 600);
+
+        let grid = &grid;
+        let rows = |material| (0..32).filter(move |&y| (0..16).any(|x| grid.material_at(x, y) == Some(material)));
+        let lowest_oil = rows(Material::Oil).max().unwrap();
+        let highest_water = rows(Material::Water).min().unwrap();
+        assert!(lowest_oil < highest_water, "oil down to row {lowest_oil}, water up to row {highest_water}");
+    }
+
+    fn cells_of(grid: &SandGrid, material: Material) -> Vec<(usize, usize)> {
+        (0..grid.height).flat_map(|y| (0..grid.width).map(move |x| (x, y))).filter(|&(x, y)| grid.material_at(x, y) == Some(material)).collect()
+    }
+
+    #[test]
+    fn fill_rect_is_clipped_to_the_grid() {
+        let mut grid = grid(8, 8);
+        grid.fill_rect(6, 5, 10, 10, Material::Stone);
+        assert_eq!(2 * 3, grid.grain_count());
+        assert!(cells_of(&grid, Material::Stone).iter().all(|&(x, y)| x >= 6 && y >= 5));
+        //starting past the grid does nothing
+        grid.fill_rect(8, 0, 4, 4, Material::Sand);
+        grid.fill_rect(0, usize::MAX, 4, 4, Material::Sand);
+        assert_eq!(6, grid.grain_count());
+    }
+
+    #[test]
+    fn draw_line_sets_the_cells_between_its_ends() {
+        let mut grid = grid(8, 8);
+        grid.draw_line((1, 1), (6, 4), Material::Stone);
+        let cells = cells_of(&grid, Material::Stone);
+        assert_eq!(6, cells.len());
+        assert!(cells.contains(&(1, 1)) && cells.contains(&(6, 4)));
+
+        //only the part inside of the grid is drawn
+        grid.clear();
+        grid.draw_line((-4, 2), (11, 2), Material::Stone);
+        assert_eq!((0..8).map(|x| (x, 2)).collect::<Vec<_>>(), cells_of(&grid, Material::Stone));
+    }
+
+    #[test]
+    fn spawn_column_stacks_from_the_bottom() {
+        let mut grid = grid(8, 8);
+        grid.spawn_column(3, 3, Material::Sand);
+        assert_eq!(vec![(3, 5), (3, 6), (3, 7)], cells_of(&grid, Material::Sand));
+        //taller than the grid, it fills the column
+        grid.spawn_column(5, 20, Material::Sand);
+        assert_eq!(8, grid.column_height(5));
+        assert_eq!(11, grid.grain_count());
+    }
+
+    #[test]
+    fn sand_rests_on_glass_and_shows_through_it() {
+        let mut grid = grid(32, 32);
+        grid.set_color_mode(ColorMode::Material);
+        //a glass tank, sand poured into it
+        grid.fill_rect(4, 28, 24, 1, Material::Glass);
+        grid.fill_rect(4, 12, 1, 16, Material::Glass);
+        grid.fill_rect(27, 12, 1, 16, Material::Glass);
+        grid.fill_rect(10, 0, 12, 10, Material::Sand);
+        let glass = cells_of(&grid, Material::Glass);
+        assert_conserved(&mut grid, 300);
+
+        //the glass didn't move and held the sand
+        assert_eq!(glass, cells_of(&grid, Material::Glass));
+        let sand = cells_of(&grid, Material::Sand);
+        assert_eq!(12 * 10, sand.len());
+        assert!(sand.iter().all(|&(x, y)| (5..27).contains(&x) && (12..28).contains(&y)), "sand outside of the tank");
+
+        //the sand is drawn opaque, the glass lets it and the background show through
+        assert!(sand.iter().all(|&(x, y)| grid.color.get_pixel(x, y).3 == 255));
+        assert!(glass.iter().all(|&(x, y)| grid.color.get_pixel(x, y).3 < 255));
+    }
+
+    #[test]
+    fn kinetic_energy_decays_once_left_alone() {
+        //a heap dropped on the floor of the grid, the grains that end up on it keep no speed
+        let mut grid = grid(48, 48);
+        grid.fill_rect(12, 0, 24, 16, Material::Sand);
+        assert!(grid.total_kinetic_energy() > 0.0);
+
+        run(&mut grid, 30);
+        let falling = grid.total_kinetic_energy();
+        assert!(falling > 1.0, "the sand should be falling, energy {falling}");
+        run(&mut grid, 1000);
+        let settled = grid.total_kinetic_energy();
+        assert!(settled < 0.01 * falling, "energy went from {falling} to {settled}");
+    }
+
+    #[test]
+    fn every_boundary_and_gravity_keeps_its_grains() {
+        use EdgeMode::{Open, Solid, Wrap};
+        let boundaries = [
+            BoundaryMode::SOLID,
+            BoundaryMode::WRAP,
+            BoundaryMode::FUNNEL,
+            BoundaryMode::uniform(Open),
+            BoundaryMode { edges: [Wrap, Solid, Wrap, Solid] },
+            BoundaryMode { edges: [Wrap, Open, Solid, Wrap] },
+        ];
+        let gravities = [Vec2::new(0.0, 9.81), Vec2::new(0.0, -9.81), Vec2::new(9.81, 0.0), Vec2::new(-9.81, 0.0), Vec2::new(7.0, 7.0)];
+        for boundary in boundaries {
+            for gravity in gravities {
+                let mut grid = grid(32, 32);
+                grid.boundary = boundary;
+                grid.gravity = gravity;
+                grid.fill_rect(8, 20, 12, 2, Material::Stone);
+                grid.fill_rect(4, 4, 20, 12, Material::Sand);
+                grid.fill_rect(22, 18, 8, 6, Material::Water);
+                //what goes out through an open edge is drained, not lost
+                let grains = grid.grain_count();
+                let mut drained = 0;
+                for tick in 0..120 {
+                    grid.simulate(DT);
+                    drained += grid.stats().drained;
+                    assert_eq!(grains, grid.grain_count() + drained, "{boundary:?} with gravity {gravity} on tick {tick}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wetness_stops_updating_once_nothing_changes() {
+        //a floor of sand next to a floor of water, neither can move
+        let mut grid = grid(16, 16);
+        grid.fill_rect(0, 15, 8, 1, Material::Sand);
+        grid.fill_rect(8, 15, 8, 1, Material::Water);
+        run(&mut grid, 30);
+        assert_eq!(Some(1.0), grid.wetness_at(7, 15));
+        assert_eq!(Some(0.0), grid.wetness_at(6, 15));
+        assert!(!grid.wetness_changing, "soaked and dry sand left the pass running");
+
+        //taking the water away starts it again
+        grid.fill_rect(8, 15, 8, 1, Material::Empty);
+        grid.simulate(DT);
+        assert!(grid.wetness_at(7, 15).unwrap() < 1.0);
+        assert!(grid.wetness_changing);
+    }
+
+    #[test]
+    fn only_settled_grains_age() {
+        let mut grid = grid(8, 64);
+        grid.set_aging(true);
+        grid.fill_rect(0, 63, 8, 1, Material::Stone);
+        grid.set_material_at(4, 0, Material::Sand);
+        let age = |grid: &SandGrid| {
+            let (x, y) = cells_of(grid, Material::Sand)[0];
+            grid.age[grid.coord_to_index(x, y)]
+        };
+        for tick in 0.. {
+            grid.simulate(DT);
+            if grid.material_at(4, 62) == Some(Material::Sand) {
+                break;
+            }
+            assert!(tick < 300, "the grain never landed");
+            assert_eq!(0, age(&grid), "the grain aged while falling, on tick {tick}");
+        }
+        run(&mut grid, 10);
+        assert!(age(&grid) > 0, "the grain didn't age once it landed");
+    }
+
+    #[test]
+    fn aging_stops_at_the_end_of_the_colormap() {
+        let mut grid = grid(32, 32);
+        grid.set_aging(true);
+        grid.set_aging_colormap(vec![(0.0, [255, 255, 255]), (20.0, [128, 128, 128])]);
+        grid.fill_rect(0, 24, 32, 8, Material::Stone);
+        run(&mut grid, 30);
+        assert_eq!(20, grid.age[grid.coord_to_index(0, 31)]);
+        assert!(grid.aged_chunks.iter().all(|&aged| aged));
+
+        //a grain set on it starts its chunk over
+        grid.set_material_at(5, 23, Material::Sand);
+        assert!(!grid.aged_chunks[grid.chunk_index(5, 23)]);
+    }
+}
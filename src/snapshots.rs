@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+
+use rust_sand_sim::sand_grid::{GridRegion, SandGrid};
+
+// State of the whole grid after a tick
+pub struct Snapshot {
+    pub tick: u64,
+    cells: GridRegion,
+}
+
+impl Snapshot {
+    // A new grid in the state of the snapshot with the settings of `live`, which
+    // is left alone so the simulation carries on from it unchanged
+    pub fn to_grid(&self, live: &SandGrid) -> SandGrid {
+        let mut grid = SandGrid::new(live.width, live.height).expect("a grid the size of a valid one is valid");
+        grid.copy_settings_from(live);
+        grid.paste_region(&self.cells);
+        grid.mark_all_dirty();
+        grid
+    }
+}
+
+// The last `capacity` states of the grid, one every `interval` ticks, oldest
+// first. Every snapshot copies every cell, about 23 bytes each, so large grids
+// want a small capacity or a longer interval.
+pub struct Snapshots {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize, //0 never takes any
+    interval: u64,
+}
+
+impl Snapshots {
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        Self { snapshots: VecDeque::with_capacity(capacity), capacity, interval: interval.max(1) }
+    }
+
+    // Takes a snapshot of `grid` if `tick` falls on the interval, forgetting
+    // the oldest one when full
+    pub fn capture(&mut self, tick: u64, grid: &SandGrid) {
+        if self.capacity == 0 || !tick.is_multiple_of(self.interval) {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot { tick, cells: grid.copy_region(0, 0, grid.width, grid.height) });
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Snapshot> {
+        self.snapshots.get(index)
+    }
+
+    // Index of the latest snapshot taken before `tick`, None without any
+    pub fn latest_before(&self, tick: u64) -> Option<usize> {
+        self.snapshots.iter().rposition(|snapshot| snapshot.tick < tick)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
@@ -2,10 +2,118 @@ use anyhow::Result;
 
 use crate::utils;
 
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    mip_generator: Option<MipGenerator>, //only for textures with a mip chain
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureOptions {
+    pub filter: wgpu::FilterMode, //magnification and minification, Nearest keeps texels crisp
+    pub mipmaps: bool, //allocates and generates the full mip chain, see `Texture::regenerate_mips`
+    pub format: wgpu::TextureFormat, //the bytes handed in are in this format, Rgba16Float for HDR colors
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            filter: wgpu::FilterMode::Linear,
+            mipmaps: false,
+            format: FORMAT,
+        }
+    }
+}
+
+// Fills every mip level of a texture by drawing the previous level into it,
+// halving the size each time. Texels are weighted by their alpha so transparent
+// cells don't bleed their color into the smaller levels.
+struct MipGenerator {
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl MipGenerator {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/blit.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mip Generation Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self { pipeline, sampler }
+    }
+
+    fn generate(&self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let bind_group_layout = self.pipeline.get_bind_group_layout(0);
+        let views: Vec<wgpu::TextureView> = (0..texture.mip_level_count())
+            .map(|level| texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            }))
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Mip Generation") });
+        for pair in views.windows(2) {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&pair[0]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+                label: None,
+            });
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &pair[1],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
 }
 
 impl Texture {
@@ -16,37 +124,69 @@ impl Texture {
         height: usize,
         data: &[u8]
     ) -> Result<Self> {
+        Self::from_bytes_with_options(device, queue, width, height, data, &TextureOptions::default())
+    }
+
+    // Same as `from_bytes` with the full mip chain allocated and generated,
+    // `regenerate_mips` has to be called whenever the pixels change
+    pub fn from_bytes_with_mips(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: usize,
+        height: usize,
+        data: &[u8]
+    ) -> Result<Self> {
+        let options = TextureOptions { mipmaps: true, ..Default::default() };
+        Self::from_bytes_with_options(device, queue, width, height, data, &options)
+    }
+
+    pub fn from_bytes_with_options(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: usize,
+        height: usize,
+        data: &[u8],
+        options: &TextureOptions
+    ) -> Result<Self> {
         
         let texture_extent = wgpu::Extent3d {
             width: width as _,
             height: height as _,
             depth_or_array_layers: 1,
         };
+        let (mip_level_count, usage) = if options.mipmaps {
+            (
+                texture_extent.max_mips(wgpu::TextureDimension::D2),
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        } else {
+            (1, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC)
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size: texture_extent,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format: options.format,
+            usage,
             view_formats: &[],
         });
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         queue.write_texture(
             texture.as_image_copy(),
-            &data,
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(width as u32 * 4),
+                bytes_per_row: Some(width as u32 * Self::block_size(options.format)),
                 rows_per_image: None,
             },
             texture_extent,
         );
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: options.filter,
+            min_filter: options.filter,
             mipmap_filter: wgpu::FilterMode::Linear,
             address_mode_u: wgpu::AddressMode::Repeat,    
             address_mode_v: wgpu::AddressMode::Repeat,
@@ -56,13 +196,25 @@ impl Texture {
             anisotropy_clamp: 1,
             ..Default::default()
         });
+        let mip_generator = (mip_level_count > 1).then(|| MipGenerator::new(device, options.format));
+        if let Some(generator) = &mip_generator {
+            generator.generate(device, queue, &texture);
+        }
         Ok(Self{
             texture,
             view: texture_view,
             sampler,
+            mip_generator,
         })
     }
 
+    // Rebuilds the mip levels from the first one, does nothing without a mip chain
+    pub fn regenerate_mips(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(generator) = &self.mip_generator {
+            generator.generate(device, queue, &self.texture);
+        }
+    }
+
     pub fn load_texture(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -72,6 +224,11 @@ impl Texture {
         Self::from_bytes(device, queue, width as _, height as _, &data)
     }
 
+    // Bytes per texel of the uncompressed formats used here
+    fn block_size(format: wgpu::TextureFormat) -> u32 {
+        format.block_copy_size(None).expect("Texture formats used are uncompressed")
+    }
+
     pub fn set_pixels(&self, queue: &wgpu::Queue, pixels: &[u8]) -> Result<()> {
         let texture_extent = wgpu::Extent3d {
             width: self.texture.size().width,
@@ -83,7 +240,81 @@ impl Texture {
             pixels,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(self.texture.size().width as u32 * 4),
+                bytes_per_row: Some(self.texture.size().width * Self::block_size(self.texture.format())),
+                rows_per_image: None,
+            },
+            texture_extent,
+        );
+        Ok(())
+    }
+
+    // Copies the first mip level back from the GPU as tightly packed rows in the
+    // texture format, blocks until the copy is done
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<u8>> {
+        let size = self.texture.size();
+        let row_size = size.width as u64 * Self::block_size(self.texture.format()) as u64;
+        //copies to buffers need rows aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+        let padded_row_size = utils::align_buffer_size(row_size, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture Read Back Buffer"),
+            size: padded_row_size * size.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Texture Read Back") });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size as u32),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: size.width, height: size.height, depth_or_array_layers: 1 },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let padded = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row_size * size.height as u64) as usize);
+        for row in padded.chunks_exact(padded_row_size as usize) {
+            pixels.extend_from_slice(&row[..row_size as usize]);
+        }
+        drop(padded);
+        buffer.unmap();
+        Ok(pixels)
+    }
+
+    // `pixels` holds the whole texture, only the given region of it is uploaded
+    pub fn set_sub_region(&self, queue: &wgpu::Queue, x: usize, y: usize, width: usize, height: usize, pixels: &[u8]) -> Result<()> {
+        let texture_width = self.texture.size().width as usize;
+        let block_size = Self::block_size(self.texture.format()) as usize;
+        let texture_extent = wgpu::Extent3d {
+            width: width as _,
+            height: height as _,
+            depth_or_array_layers: 1,
+        };
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: x as _, y: y as _, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::ImageDataLayout {
+                offset: ((y * texture_width + x) * block_size) as _,
+                bytes_per_row: Some((texture_width * block_size) as u32),
                 rows_per_image: None,
             },
             texture_extent,
@@ -9,30 +9,29 @@ use wgpu::util::DeviceExt;
 
 pub(crate) unsafe fn slice_as_u8_slice<T: Sized>(any: &[T]) -> &[u8] {
     let ptr = (any as *const [T]) as *const u8;
-    std::slice::from_raw_parts(ptr, any.len() * std::mem::size_of::<T>())
+    std::slice::from_raw_parts(ptr, std::mem::size_of_val(any))
 }
 
 pub fn align_buffer_size(size: u64, alignment: u64) -> u64 {
-    return (size + alignment - 1) & !(alignment - 1);
+    (size + alignment - 1) & !(alignment - 1)
 }
 
 pub fn load_texture(file_name: &str) -> Result<(u32, u32, u64, Vec<u8>)> {
-    let image = File::open(file_name)?;
-
-    let decoder = png::Decoder::new(image);
-    let mut reader = decoder.read_info()?;
+    let (width, height, pixels) = rust_sand_sim::cpu_texture::decode_png(file_name)?;
+    let size = pixels.len() as u64;
+    Ok((width, height, size, pixels))
+}
 
-    let mut pixels = vec![0;  reader.info().raw_bytes()];
-    reader.next_frame(&mut pixels)?;
+pub fn save_png(file_name: &std::path::Path, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+    let file = File::create(file_name)?;
 
-    let size = reader.info().raw_bytes() as u64;
-    let (width, height) = reader.info().size();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
 
-    if /*width != 1024 || height != 1024 ||*/ reader.info().color_type != png::ColorType::Rgba {
-        panic!("Invalid texture image.");
-    }
-
-    Ok((width, height, size, pixels))
+    Ok(())
 }
 
 pub fn load_model_data(indices: &mut Vec<u32>, vertices: &mut Vec<Vertex>) -> Result<()> {
@@ -62,6 +61,7 @@ pub fn load_model_data(indices: &mut Vec<u32>, vertices: &mut Vec<Vertex>) -> Re
                     model.mesh.texcoords[tex_coord_offset],
                     1.0 - model.mesh.texcoords[tex_coord_offset + 1],
                 ),
+                normal: Vertex::DEFAULT_NORMAL,
             };
 
             if let Some(index) = unique_vertices.get(&vertex) {
@@ -80,8 +80,9 @@ pub fn load_model_data(indices: &mut Vec<u32>, vertices: &mut Vec<Vertex>) -> Re
 }
 
 
+// View from `pos` looking along the y axis rotated by `quat`
 pub(crate) fn get_view_matrix(pos: Vec3, quat: Quat) -> glam::Mat4 {
-    let center = quat * Vec3::new(0.0, 1.0, 0.0);
+    let center = pos + quat * Vec3::new(0.0, 1.0, 0.0);
     glam::Mat4::look_at_rh(
         pos,
         center,
@@ -89,14 +90,19 @@ pub(crate) fn get_view_matrix(pos: Vec3, quat: Quat) -> glam::Mat4 {
     )
 }
 
-pub(crate) fn create_perspective_matrix(aspect_ratio: f32) -> glam::Mat4 {
-    glam::Mat4::perspective_rh(consts::FRAC_PI_4, aspect_ratio, 1.0, 100.0)
+pub(crate) fn create_perspective_matrix(aspect_ratio: f32, far: f32) -> glam::Mat4 {
+    glam::Mat4::perspective_rh(consts::FRAC_PI_4, aspect_ratio, 1.0, far)
 }
 
 pub(crate) fn create_iso_matrix(width: f32, height: f32) -> glam::Mat4 {
     glam::Mat4::orthographic_rh(0.0, width, height, 0.0, 1.0, 100.0)
 }
 
+// Orthographic projection showing the width x height area whose top left corner is `origin`
+pub(crate) fn create_iso_view_matrix(origin: Vec2, width: f32, height: f32) -> glam::Mat4 {
+    glam::Mat4::orthographic_rh(origin.x, origin.x + width, origin.y + height, origin.y, 1.0, 100.0)
+}
+
 pub(crate) fn create_matrix_buffer_and_bind_group(device: &wgpu::Device, label: &str, bind_group_layout: &wgpu::BindGroupLayout, matrix: &glam::Mat4) -> (wgpu::Buffer, wgpu::BindGroup) {
     let matrix_ref: &[f32; 16] = matrix.as_ref();
     let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -106,7 +112,7 @@ pub(crate) fn create_matrix_buffer_and_bind_group(device: &wgpu::Device, label:
     });
     
     let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &bind_group_layout,
+        layout: bind_group_layout,
         entries: &[
             wgpu::BindGroupEntry {
                 binding: 0,
@@ -118,47 +124,31 @@ pub(crate) fn create_matrix_buffer_and_bind_group(device: &wgpu::Device, label:
     (uniform_buf, uniform_bind_group)
 }
 
-pub fn new_texture(width:usize, height: usize) -> Vec<u8> {
-    let size = (width * height * 4) as _; //RGBA assumed
-    let mut res = Vec::<u8>::with_capacity(size);
-    res.resize(size, 255);
-
-    /*for y  in  0..height {
-        let v_ratio = y as f32 / height as f32; 
-        let g = (v_ratio * 255.0).round() as u8;
-        for x in 0..width {
-            let i = (y*width + x) * 4;
-            let h_ratio = x as f32 / width as f32;
-
-            let r = (h_ratio * 255.0) as u8;
-            //let g = (v_ratio * 255.0).round() as u8;
-            let b = 255 as u8;
-            let a = 255 as u8;
-            
-            res[i + 0] = r;
-            res[i + 1] = g;
-            res[i + 2] = b;
-            res[i + 3] = a;
-        }
-    }*/
-
-
-    res
+// Per instance vertex buffer for `ModelDrawer::draw_model_instanced`, one matrix per instance
+pub(crate) fn create_instance_buffer(device: &wgpu::Device, label: &str, transforms: &[glam::Mat4]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(format!("{label} Instance Buffer").as_str()),
+        contents: bytemuck::cast_slice(transforms),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
 }
 
-
 #[repr(C)]
 #[derive(Copy, Clone, Debug, NoUninit)]
 pub struct Vertex {
     pub(crate) pos: Vec3,
     pub(crate) color: Vec3,
     pub(crate) tex_coord: Vec2,
+    pub(crate) normal: Vec3,
 }
 
 impl Vertex {
+    // Facing the camera, which looks down the y axis at the y = 0 plane
+    pub const DEFAULT_NORMAL: Vec3 = Vec3::NEG_Y;
+
     pub const fn new(pos: Vec3, tex_coord: Vec2) -> Self {
         let color = Vec3::new(1.0, 1.0, 1.0);
-        Self { pos, color, tex_coord }
+        Self { pos, color, tex_coord, normal: Self::DEFAULT_NORMAL }
     }
 }
 
@@ -167,6 +157,7 @@ impl PartialEq for Vertex {
         self.pos == other.pos
             && self.color == other.color
             && self.tex_coord == other.tex_coord
+            && self.normal == other.normal
     }
 }
 
@@ -182,6 +173,9 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
     }
 }
 
@@ -1,3 +1,4 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use wgpu::{Instance, Surface};
@@ -54,6 +55,20 @@ pub trait App: 'static + Sized {
         queue: &wgpu::Queue,
     );
 
+    // Called once a lost device was replaced, every GPU resource has to be
+    // created again on the new one. Starting over with a new app does that but
+    // loses its state, apps keeping theirs override it.
+    fn device_recreated(
+        &mut self,
+        window: Arc<Window>,
+        config: &wgpu::SurfaceConfiguration,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        *self = Self::init(window, config, adapter, device, queue);
+    }
+
     fn process_event(&mut self, event: &Event<()>);
 
     fn update(&mut self, input: &WinitInputHelper);
@@ -62,7 +77,7 @@ pub trait App: 'static + Sized {
 }
 
 // Initialize logging in platform dependant ways.
-fn init_logger() {
+pub fn init_logger() {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             // As we don't have an environment to pull logging level from, we use the query string.
@@ -105,7 +120,7 @@ struct EventLoopWrapper {
 }
 
 impl EventLoopWrapper {
-    pub fn new(title: &str) -> Self {
+    pub fn new(title: &str, size: Option<PhysicalSize<u32>>) -> Self {
         let event_loop = EventLoop::new().unwrap();
         let mut builder = winit::window::WindowBuilder::new();
         #[cfg(target_arch = "wasm32")]
@@ -123,6 +138,9 @@ impl EventLoopWrapper {
             builder = builder.with_canvas(Some(canvas));
         }
         builder = builder.with_title(title);
+        if let Some(size) = size {
+            builder = builder.with_inner_size(size);
+        }
         let window = Arc::new(builder.build(&event_loop).unwrap());
 
         Self { event_loop, window }
@@ -248,6 +266,13 @@ impl SurfaceWrapper {
         }
     }
 
+    /// Drop the surface and its configuration, before creating a new one for
+    /// the same window on a new device.
+    fn release(&mut self) {
+        self.surface = None;
+        self.config = None;
+    }
+
     /// On suspend on android, we drop the surface, as it's no longer valid.
     ///
     /// A suspend event is always followed by at least one resume event.
@@ -257,7 +282,7 @@ impl SurfaceWrapper {
         }
     }
 
-    fn get(&self) -> Option<&Surface> {
+    fn get(&self) -> Option<&Surface<'_>> {
         self.surface.as_ref()
     }
 
@@ -272,6 +297,7 @@ struct ExampleContext {
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    lost: Arc<AtomicBool>, //set by the device lost callback, the device can't be used anymore
 }
 impl ExampleContext {
     /// Initializes the example context.
@@ -337,13 +363,37 @@ impl ExampleContext {
             .await
             .expect("Unable to find a suitable GPU adapter!");
 
+        // A driver reset or a sleep/wake cycle can lose the device, the event loop
+        // then replaces it. Errors from the lost device are expected until it does.
+        let lost = Arc::new(AtomicBool::new(false));
+        let lost_flag = lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            if !matches!(reason, wgpu::DeviceLostReason::Dropped) {
+                log::error!("GPU device lost ({reason:?}): {message}");
+                lost_flag.store(true, Ordering::Relaxed);
+            }
+        });
+        let lost_flag = lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if lost_flag.load(Ordering::Relaxed) {
+                log::warn!("Ignoring an error of the lost GPU device: {error}");
+                return;
+            }
+            panic!("wgpu error: {error}");
+        }));
+
         Self {
             instance,
             adapter,
             device,
             queue,
+            lost,
         }
     }
+
+    fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
 }
 
 struct FrameCounter {
@@ -398,9 +448,7 @@ impl Timer {
 }
 
 
-async fn start<E: App>(title: &str) {
-    init_logger();
-
+async fn start<E: App>(title: &str, size: Option<PhysicalSize<u32>>) {
     log::debug!(
         "Enabled backends: {:?}",
         wgpu::Instance::enabled_backend_features()
@@ -408,9 +456,9 @@ async fn start<E: App>(title: &str) {
 
     let mut input = WinitInputHelper::new();
 
-    let window_loop = EventLoopWrapper::new(title);
+    let window_loop = EventLoopWrapper::new(title, size);
     let mut surface = SurfaceWrapper::new();
-    let context = ExampleContext::init_async::<E>(&mut surface, window_loop.window.clone()).await;
+    let mut context = ExampleContext::init_async::<E>(&mut surface, window_loop.window.clone()).await;
     let mut frame_counter = FrameCounter::new();
 
     // We wait to create the example until we have a valid surface.
@@ -437,8 +485,8 @@ async fn start<E: App>(title: &str) {
 
             input_timer.start();
             _ = input.update(&event);
-            if example.is_some() {
-                example.as_mut().unwrap().process_event(&event);
+            if let Some(example) = example.as_mut() {
+                example.process_event(&event);
             }
             
             input_acc_ms += input_timer.get_elapsed_time();
@@ -503,6 +551,28 @@ async fn start<E: App>(title: &str) {
                             return;
                         }
 
+                        if context.is_lost() {
+                            cfg_if::cfg_if! {
+                                if #[cfg(target_arch = "wasm32")] {
+                                    // The device can't be requested again without blocking, give up drawing.
+                                    return;
+                                } else {
+                                    log::warn!("Recreating the lost GPU device");
+                                    // The old surface has to go before the window gets a new one.
+                                    surface.release();
+                                    context = pollster::block_on(ExampleContext::init_async::<E>(&mut surface, window_loop.window.clone()));
+                                    surface.resume(&context, window_loop.window.clone(), E::SRGB);
+                                    example.as_mut().unwrap().device_recreated(
+                                        window_loop.window.clone(),
+                                        surface.config(),
+                                        &context.adapter,
+                                        &context.device,
+                                        &context.queue,
+                                    );
+                                }
+                            }
+                        }
+
                         timer.start();
                         example.as_mut().unwrap().update(&input);
                         let update_elapsed=timer.get_elapsed_time();
@@ -547,12 +617,15 @@ async fn start<E: App>(title: &str) {
     );
 }
 
-pub fn run<E: App>(title: &'static str) {
+// Opens a window of `size`, or the OS default size, and runs the app in it.
+// Call `init_logger` first.
+pub fn run<E: App>(title: &str, size: Option<PhysicalSize<u32>>) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
-            wasm_bindgen_futures::spawn_local(async move { start::<E>(title).await })
+            let title = title.to_owned();
+            wasm_bindgen_futures::spawn_local(async move { start::<E>(&title, size).await })
         } else {
-            pollster::block_on(start::<E>(title));
+            pollster::block_on(start::<E>(title, size));
         }
     }
 }